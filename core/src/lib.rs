@@ -1,9 +1,19 @@
-use anyhow::anyhow;
-use exe::{Buffer, Error, ImageDirectoryEntry, ImageSectionHeader, PE, PEType, RVA, SectionCharacteristics, VecPE};
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+use anyhow::{anyhow, Context};
+use exe::{Buffer, Error, ExportDirectory, ImageDirectoryEntry, ImageSectionHeader, PE, PEType, RVA, SectionCharacteristics,
+    ThunkData, VecPE};
+use guardian_sdk::{BEGIN_MARKER, END_MARKER, MARKER_JMP};
 use iced_x86::code_asm::CodeAssembler;
 use include_crypt::{EncryptedFile, include_crypt};
 
 use crate::pe::parser::MapFile;
+use crate::pe::pdata::ExceptionTable;
+use crate::pe::pdb_file::PdbFile;
+use crate::pe::symbols::{FunctionSelector, FunctionSymbol, SymbolProvider};
+use crate::virtualizer::cfg::ControlFlowGraph;
 use crate::virtualizer::disassembler::convert_to_threaded_code;
 use crate::virtualizer::Virtualizer;
 
@@ -17,23 +27,52 @@ const VM: EncryptedFile =
 const VM_THREADED: EncryptedFile =
     include_crypt!("..\\target\\x86_64-pc-windows-msvc\\release\\vm_threaded.dll");
 
+/// Size of the `push bytecode; jmp vm` written over the entry of a virtualized routine
+const PATCH_LEN: usize = 10;
+
 pub struct Obfuscator {
     pe: VecPE,
     path: String,
     path_out: String,
-    map_file: Option<MapFile>,
+    symbols: Option<Box<dyn SymbolProvider>>,
+    // functions matched by these are never added by name
+    excluded: Vec<FunctionSelector>,
     obfuscation: bool,
+    pdata: bool,
+    exception_table: Option<ExceptionTable>,
     functions: Vec<Routine>,
+    mismatches: Vec<BoundsMismatch>,
 }
 
 struct Routine {
     rva: RVA,
     len: usize,
+    // separated chunks of the function, e.g. cold code moved out of line
+    chunks: Vec<Range<u32>>,
+    // code between the markers of a marked region, the routine itself spans the markers which are erased
+    region: Option<Range<u32>>,
+}
+
+/// A function whose .pdata bounds disagree with the size from the map file or pdb
+#[derive(Debug, Clone)]
+pub struct BoundsMismatch {
+    pub function: String,
+    pub pdata: Range<u32>,
+    pub symbol: Range<u32>,
+}
+
+impl fmt::Display for BoundsMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bounds of '{}' differ, .pdata {:#x}..{:#x}, symbol {:#x}..{:#x}",
+            self.function, self.pdata.start, self.pdata.end, self.symbol.start, self.symbol.end)
+    }
 }
 
 struct VirtualizedRoutine {
     routine: Routine,
     bytecode_rva: RVA,
+    // rvas of data inside the routine that is still read by the bytecode
+    data: Vec<Range<u32>>,
 }
 
 trait PeExt {
@@ -53,7 +92,8 @@ impl PeExt for VecPE {
 
 impl Obfuscator {
     pub fn new(path: String, path_out: String) -> Result<Obfuscator, exe::Error> {
-        Ok(Self { pe: VecPE::from_disk_file(&path)?, path, path_out, map_file: None, obfuscation: false, functions: Vec::new() })
+        Ok(Self { pe: VecPE::from_disk_file(&path)?, path, path_out, symbols: None, excluded: Vec::new(), obfuscation: false,
+            pdata: true, exception_table: None, functions: Vec::new(), mismatches: Vec::new() })
     }
 
     /// Path of pe to obfuscate
@@ -72,25 +112,241 @@ impl Obfuscator {
         self.obfuscation = enable;
     }
 
+    /// Resolve function bounds from the .pdata section instead of the symbol sizes, which are the distance
+    /// to the next symbol for map files. Enabled by default
+    pub fn use_pdata(&mut self, enable: bool) {
+        self.pdata = enable;
+    }
+
+    /// Functions whose .pdata bounds disagreed with the symbols, their .pdata bounds were used
+    pub fn bounds_mismatches(&self) -> &[BoundsMismatch] {
+        &self.mismatches
+    }
+
+    /// Bounds of the functions added so far, without their separated chunks
+    pub fn routines(&self) -> impl Iterator<Item = Range<u32>> + '_ {
+        self.functions.iter().map(|function| function.rva.0..function.rva.0 + function.len as u32)
+    }
+
     pub fn with_map_file(mut self, map_path: String) -> Self {
         let map_data = std::fs::read(map_path).unwrap();
         let map_string = String::from_utf8(map_data).unwrap();
         let map_file = MapFile::load(&map_string).unwrap();
-        self.map_file = Some(map_file);
+        self.symbols = Some(Box::new(map_file));
+        self
+    }
+
+    /// Locate functions with the procedures and public symbols of a pdb instead of a map file
+    pub fn with_pdb(mut self, pdb_path: String) -> Self {
+        let pdb_file = PdbFile::load(&pdb_path).unwrap();
+        self.symbols = Some(Box::new(pdb_file));
         self
     }
 
-    pub fn add_function(&mut self, function: String) -> anyhow::Result<()> {
-        let map_file = self.map_file.as_ref()
-            .ok_or(anyhow!("no map file provided"))?;
-        let (function, function_size) = map_file.get_function(&function)
-            .ok_or(anyhow!("couldn't find function '{function}'"))?;
-        self.functions.push(Routine { rva: RVA(function.rva.0 as u32), len: function_size });
-        Ok(())
+    /// Virtualize all functions matched by a [`FunctionSelector`] that aren't excluded, returns the matches
+    pub fn add_function(&mut self, function: String) -> anyhow::Result<Vec<FunctionSymbol>> {
+        let selector = FunctionSelector::parse(&function)?;
+        let symbols = self.symbols.as_ref()
+            .ok_or(anyhow!("no map file or pdb provided"))?;
+        let mut matches = symbols.functions(&selector);
+        anyhow::ensure!(!matches.is_empty(), "couldn't find function '{function}'");
+
+        let excluded = self.excluded.iter()
+            .flat_map(|excluded| symbols.functions(excluded))
+            .map(|symbol| symbol.rva)
+            .collect::<HashSet<_>>();
+        matches.retain(|symbol| !excluded.contains(&symbol.rva));
+        anyhow::ensure!(!matches.is_empty(), "all functions matching '{function}' are excluded");
+
+        for symbol in matches.iter() {
+            // overlapping selectors must not patch a function twice
+            if !self.functions.iter().any(|function| function.rva.0 == symbol.rva) {
+                self.add_symbol(symbol.name.clone(), symbol.clone())?;
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Skip the functions matched by `selector` in functions added by name afterwards
+    pub fn exclude(&mut self, selector: &str) -> anyhow::Result<()> {
+        self.excluded.push(FunctionSelector::parse(selector)?);
+        ok()
+    }
+
+    /// Virtualize an exported function, exports have no size so it ends at the next known function
+    pub fn add_export(&mut self, name: String) -> anyhow::Result<()> {
+        let exports = ExportDirectory::parse(&self.pe)?;
+        let export_map = exports.get_export_map(&self.pe)?;
+        let rva = match export_map.get(name.as_str()) {
+            Some(ThunkData::Function(rva)) => rva.0,
+            Some(_) => anyhow::bail!("export '{name}' is forwarded to another module"),
+            None => anyhow::bail!("couldn't find export '{name}'"),
+        };
+        // exports passed twice or also selected by name must not patch a function twice
+        if self.functions.iter().any(|function| function.rva.0 == rva) {
+            return ok();
+        }
+        let next_export = export_map.values()
+            .filter_map(|thunk| match thunk {
+                ThunkData::Function(export) if export.0 > rva => Some(export.0),
+                _ => None,
+            })
+            .min();
+
+        let section = self.pe.get_section_by_rva(RVA(rva))?;
+        let section_end = section.virtual_address.0 + section.virtual_size;
+        let next_function = self.exception_table()?.next_function(rva);
+        let end = [next_export, next_function].into_iter().flatten().fold(section_end, u32::min);
+        anyhow::ensure!((end - rva) as usize >= PATCH_LEN, "export '{name}' at {rva:#x} is too small to patch, \
+            it ends at {end:#x}");
+
+        self.add_symbol(name.clone(), FunctionSymbol { name, rva, size: (end - rva) as usize, exact: false })
+    }
+
+    /// Virtualize `len` bytes of code at `start` as is, the bounds aren't checked against symbols or .pdata
+    pub fn add_rva_range(&mut self, start: u32, len: usize) -> anyhow::Result<()> {
+        let end = u32::try_from(len).ok().and_then(|len| start.checked_add(len))
+            .ok_or_else(|| anyhow!("rva range {start:#x}+{len:#x} exceeds the address space"))?;
+        anyhow::ensure!(len >= PATCH_LEN, "rva range {start:#x}..{end:#x} is too small to patch, it needs at least \
+            {PATCH_LEN} bytes");
+        let section = self.pe.get_section_by_rva(RVA(start))?;
+        anyhow::ensure!(section.characteristics.contains(SectionCharacteristics::MEM_EXECUTE),
+            "rva range {start:#x}..{end:#x} isn't code");
+        anyhow::ensure!(end <= section.virtual_address.0 + section.virtual_size,
+            "rva range {start:#x}..{end:#x} exceeds its section");
+        // a function in the range that's already added would be patched twice
+        if let Some(routine) = self.functions.iter().find(|routine| {
+            std::iter::once(routine.rva.0..routine.rva.0 + routine.len as u32).chain(routine.chunks.iter().cloned())
+                .any(|chunk| chunk.start < end && start < chunk.end)
+        }) {
+            anyhow::bail!("rva range {start:#x}..{end:#x} overlaps the routine at {:#x}", routine.rva.0);
+        }
+
+        self.functions.push(Routine { rva: RVA(start), len, chunks: Vec::new(), region: None });
+        ok()
+    }
+
+    /// Virtualize the regions enclosed by `guardian_begin!()` and `guardian_end!()` of the sdk in all code
+    /// sections, returns the number of regions found
+    pub fn add_marked_regions(&mut self) -> anyhow::Result<usize> {
+        let mut markers = Vec::new();
+        for section in self.pe.get_section_table()? {
+            if !section.characteristics.contains(SectionCharacteristics::MEM_EXECUTE) {
+                continue;
+            }
+            let size = section.size_of_raw_data.min(section.virtual_size) as usize;
+            let code = self.pe.read(section.data_offset(self.pe.get_type()), size)?;
+            for (i, window) in code.windows(BEGIN_MARKER.len()).enumerate() {
+                let rva = section.virtual_address.0 + i as u32;
+                if window == BEGIN_MARKER {
+                    markers.push((rva, true));
+                } else if window == END_MARKER {
+                    markers.push((rva, false));
+                }
+            }
+        }
+        markers.sort_unstable();
+
+        let mut regions = Vec::new();
+        let mut open = None;
+        for (rva, begin) in markers {
+            match (begin, open) {
+                (true, None) => open = Some(rva),
+                (true, Some(start)) => anyhow::bail!("begin marker at {rva:#x} inside of the region starting at \
+                    {start:#x}, regions can't be nested"),
+                (false, Some(start)) => {
+                    regions.push((start, rva));
+                    open = None;
+                }
+                (false, None) => anyhow::bail!("end marker at {rva:#x} without a begin marker"),
+            }
+        }
+        if let Some(start) = open {
+            anyhow::bail!("begin marker at {start:#x} without an end marker");
+        }
+
+        let image_base = self.pe.get_image_base()?;
+        for (begin, end) in regions.iter() {
+            // the jmp of the end marker stays in the code, it leaves the vm right after the marker
+            let code = begin + BEGIN_MARKER.len() as u32..end + MARKER_JMP.len() as u32;
+
+            // only code reachable from the begin marker is lifted but the whole region is erased, code that is
+            // only reached from outside of it would be lost
+            let offset = self.pe.rva_to_offset(RVA(code.start))?;
+            let bytes = self.pe.read(offset.into(), code.len())?;
+            let cfg = ControlFlowGraph::recover(&[(image_base + code.start as u64, bytes)], |address, len| {
+                let offset = self.pe.rva_to_offset(RVA(address.checked_sub(image_base)? as u32)).ok()?;
+                self.pe.read(offset.into(), len).ok().map(|data| data.to_vec())
+            }).with_context(|| format!("failed to recover the region at {begin:#x}"))?;
+            if let Some(gap) = cfg.data.iter()
+                .find(|data| !cfg.jump_tables.values().any(|table| table.table == **data)) {
+                anyhow::bail!("code at {:#x}..{:#x} in the region at {begin:#x} isn't reachable from its begin marker",
+                    gap.start - image_base, gap.end - image_base);
+            }
+
+            let len = (end + END_MARKER.len() as u32 - begin) as usize;
+            self.functions.push(Routine { rva: RVA(*begin), len, chunks: Vec::new(), region: Some(code) });
+        }
+        Ok(regions.len())
+    }
+
+    fn exception_table(&mut self) -> Result<&ExceptionTable, Error> {
+        if self.exception_table.is_none() {
+            self.exception_table = Some(ExceptionTable::load(&self.pe)?);
+        }
+        Ok(self.exception_table.as_ref().unwrap())
+    }
+
+    fn add_symbol(&mut self, function: String, symbol: FunctionSymbol) -> anyhow::Result<()> {
+        let (rva, function_size) = (symbol.rva, symbol.size);
+        let bounds = rva..rva + function_size as u32;
+
+        if !self.pdata {
+            self.functions.push(Routine { rva: RVA(rva), len: function_size, chunks: Vec::new(), region: None });
+            return ok();
+        }
+
+        let mut chunks = self.exception_table()?.chunks(rva);
+
+        if chunks.is_empty() && symbol.exact {
+            self.functions.push(Routine { rva: RVA(rva), len: function_size, chunks: Vec::new(), region: None });
+            return ok();
+        }
+        if chunks.is_empty() {
+            // leaf functions have no unwind info, only the padding up to the next symbol can be dropped
+            let offset = self.pe.rva_to_offset(RVA(rva))?;
+            let code = self.pe.read(offset.into(), function_size)?;
+            let len = code.iter().rposition(|byte| *byte != 0xCC).map_or(0, |last| last + 1);
+            anyhow::ensure!(len >= PATCH_LEN, "function '{function}' at {rva:#x} is too small to patch, it has \
+                {len} bytes of code");
+            self.functions.push(Routine { rva: RVA(rva), len, chunks: Vec::new(), region: None });
+            return ok();
+        }
+
+        let primary = chunks.remove(0);
+        // anything but int3 padding between the end of the function and the next symbol means the map
+        // size is off, e.g. a folded comdat or a symbol the linker didn't list
+        let matches = match primary.end < bounds.end && !symbol.exact {
+            true => {
+                let offset = self.pe.rva_to_offset(RVA(primary.end))?;
+                self.pe.read(offset.into(), (bounds.end - primary.end) as usize)?.iter().all(|byte| *byte == 0xCC)
+            }
+            false => primary.end == bounds.end,
+        };
+        if !matches {
+            self.mismatches.push(BoundsMismatch { function, pdata: primary.clone(), symbol: bounds });
+        }
+
+        self.functions.push(Routine { rva: RVA(rva), len: primary.len(), chunks, region: None });
+        ok()
     }
 
-    pub fn add_functions(&mut self, functions: Vec<String>) -> anyhow::Result<()> {
-        functions.into_iter().try_for_each(|function| self.add_function(function))
+    pub fn add_functions(&mut self, functions: Vec<String>) -> anyhow::Result<Vec<FunctionSymbol>> {
+        let mut matches = Vec::new();
+        for function in functions {
+            matches.append(&mut self.add_function(function)?);
+        }
+        Ok(matches)
     }
 
     pub fn virtualize(&mut self) -> anyhow::Result<()> {
@@ -145,7 +401,8 @@ impl Obfuscator {
                 &function.routine,
                 vm_section.virtual_address.0 + machine_entry.0 - 0x1000,
                 bytecode_section.virtual_address.0 + function.bytecode_rva.0,
-            );
+                &function.data,
+            )?;
         }
 
         self.pe.recreate_image(PEType::Disk)?;
@@ -155,43 +412,57 @@ impl Obfuscator {
 
     fn virtualize_fns(&mut self, vm_section: RVA, vm: &VecPE) -> anyhow::Result<(Vec<u8>, Vec<VirtualizedRoutine>)> {
         let mut virtualizer = Virtualizer::with_pe(self.pe.clone())?;
-        let mut bytecode = Vec::new();
-        let mut virtualized_fns = Vec::new();
-
-        for function in &self.functions {
-            let target_fn_addr = self.pe.rva_to_offset(function.rva).unwrap().0 as _;
-            // todo determine end of function correctly
-            let target_function = self.pe.get_slice_ref::<u8>(target_fn_addr, function.len).unwrap();
-            let mut virtualized_function = virtualizer.virtualize_with_ip(
-                self.pe.get_image_base().unwrap() + function.rva.0 as u64,
-                target_function,
-            )?;
-
-            virtualized_fns.push(VirtualizedRoutine {
-                routine: Routine { rva: RVA(function.rva.0), len: function.len },
-                bytecode_rva: RVA(bytecode.len() as u32),
-            });
+        let image_base = self.pe.get_image_base()?;
+
+        let routines = self.functions.iter().map(|function| {
+            let bounds = function.region.clone().unwrap_or(function.rva.0..function.rva.0 + function.len as u32);
+            std::iter::once(bounds).chain(function.chunks.iter().cloned()).map(|chunk| {
+                let offset = self.pe.rva_to_offset(RVA(chunk.start))?.0 as _;
+                let code = self.pe.get_slice_ref::<u8>(offset, chunk.len())?;
+                Ok((image_base + chunk.start as u64, code))
+            }).collect::<Result<Vec<_>, Error>>()
+        }).collect::<Result<Vec<_>, Error>>()?;
+
+        // all routines are virtualized together so calls between them stay inside the vm
+        let (bytecode, entries) = virtualizer.virtualize_chunked_routines(&routines)?;
+        let (bytecode, entries) = if self.obfuscation {
+            convert_to_threaded_code(vm, vm_section, &bytecode, &entries)?
+        } else {
+            (bytecode, entries)
+        };
 
-            if self.obfuscation {
-                let mut converted = convert_to_threaded_code(vm, vm_section, virtualized_function.as_slice())?;
-                bytecode.append(&mut converted);
-            } else {
-                bytecode.append(&mut virtualized_function);
+        let data = virtualizer.data_ranges().iter()
+            .map(|data| (data.start - image_base) as u32..(data.end - image_base) as u32)
+            .collect::<Vec<_>>();
+
+        let virtualized_fns = self.functions.iter().zip(entries).map(|(function, entry)| {
+            let bounds = function.rva.0..function.rva.0 + function.len as u32;
+            let contains = |rva: &u32| bounds.contains(rva) || function.chunks.iter().any(|chunk| chunk.contains(rva));
+            VirtualizedRoutine {
+                routine: Routine {
+                    rva: RVA(function.rva.0),
+                    len: function.len,
+                    chunks: function.chunks.clone(),
+                    region: function.region.clone(),
+                },
+                bytecode_rva: RVA(entry as u32),
+                data: data.iter().filter(|data| contains(&data.start)).cloned().collect(),
             }
-
-            virtualizer.reset();
-        }
+        }).collect();
 
         Ok((bytecode, virtualized_fns))
     }
 
-    fn patch_fn(&mut self, target_fn: &Routine, vm_rva: u32, bytecode_rva: u32) -> usize {
+    fn patch_fn(&mut self, target_fn: &Routine, vm_rva: u32, bytecode_rva: u32, data: &[Range<u32>])
+        -> anyhow::Result<usize> {
         let mut a = CodeAssembler::new(64).unwrap();
         a.push(bytecode_rva as i32).unwrap();
         // for macro support use call here instead if macro
         a.jmp(vm_rva as u64 - target_fn.rva.0 as u64).unwrap();
 
         let patch = a.assemble(0).unwrap();
+        let len = target_fn.len.checked_sub(patch.len())
+            .ok_or_else(|| anyhow!("routine at {:#x} is too small to patch", target_fn.rva.0))?;
 
         let target_fn_offset = self.pe.rva_to_offset(target_fn.rva).unwrap();
         let target_function_mut = self.pe.get_mut_slice_ref::<u8>(target_fn_offset.0 as usize, patch.len()).unwrap();
@@ -199,19 +470,31 @@ impl Obfuscator {
 
         self.remove_routine(Routine {
             rva: RVA(target_fn.rva.0 + patch.len() as u32),
-            len: target_fn.len - patch.len(),
-        });
+            len,
+            chunks: target_fn.chunks.clone(),
+            region: None,
+        }, data);
 
         self.pe.pad_to_alignment().unwrap();
         self.pe.fix_image_size().unwrap();
-        patch.len()
+        Ok(patch.len())
     }
 
-    fn remove_routine(&mut self, routine: Routine) {
-        let offset = self.pe.rva_to_offset(routine.rva).unwrap();
-        let data = vec![0xCCu8; routine.len];
-        // or copy_from_slice ?
-        self.pe.write(offset.into(), data).unwrap();
+    /// Overwrites the routine and its chunks with int3, except for the `keep` ranges
+    fn remove_routine(&mut self, routine: Routine, keep: &[Range<u32>]) {
+        let bounds = routine.rva.0..routine.rva.0 + routine.len as u32;
+        for chunk in std::iter::once(bounds).chain(routine.chunks) {
+            let offset = self.pe.rva_to_offset(RVA(chunk.start)).unwrap();
+            let mut data = self.pe.read(offset.into(), chunk.len()).unwrap().to_vec();
+            for (i, byte) in data.iter_mut().enumerate() {
+                let rva = chunk.start + i as u32;
+                if !keep.iter().any(|range| range.contains(&rva)) {
+                    *byte = 0xCC;
+                }
+            }
+            // or copy_from_slice ?
+            self.pe.write(offset.into(), data).unwrap();
+        }
     }
 }
 
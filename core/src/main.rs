@@ -15,15 +15,40 @@ struct Args {
    out: String,
    #[arg(short, long)]
    /// Path to .map file
-   map_file: String,
-   /// Array of functions names (demangled) to virtualize
-   #[clap(value_parser, num_args = 1.., value_delimiter = ',')]
+   map_file: Option<String>,
+   /// Path to .pdb file, used instead of the .map file
+   #[arg(short, long, conflicts_with = "map_file")]
+   pdb: Option<String>,
+   /// Use the symbol sizes as function bounds instead of the .pdata section
+   #[arg(long)]
+   no_pdata: bool,
+   /// Exported functions to virtualize, no symbol file needed
+   #[arg(short, long, value_delimiter = ',')]
+   export: Vec<String>,
+   /// Code to virtualize as start:len rvas, e.g. 0x1000:0x80
+   #[arg(short, long, value_delimiter = ',', value_parser = parse_rva_range)]
+   rva_range: Vec<(u32, usize)>,
+   /// Functions to skip when matched by the function selectors, repeat it for several selectors
+   #[arg(short = 'x', long)]
+   exclude: Vec<String>,
+   /// Functions to virtualize, exact demangled or mangled names, globs like license::* or regexes prefixed with
+   /// re:, each one a separate argument as names and regexes can contain commas
+   #[clap(value_parser, num_args = 0..)]
    functions: Vec<String>,
 }
 
+fn parse_rva_range(range: &str) -> Result<(u32, usize), String> {
+   let parse = |value: &str| match value.strip_prefix("0x") {
+      Some(hex) => u32::from_str_radix(hex, 16),
+      None => value.parse(),
+   }.map_err(|error| format!("invalid rva '{value}': {error}"));
+
+   let (start, len) = range.split_once(':').ok_or("expected start:len".to_owned())?;
+   Ok((parse(start)?, parse(len)? as usize))
+}
+
 fn main() {
    let args = Args::parse();
-   assert!(!args.functions.is_empty());
 
    if let Err(error) = run_guardian(args) {
       eprintln!("{}", error);
@@ -31,11 +56,37 @@ fn main() {
 }
 
 fn run_guardian(args: Args) -> anyhow::Result<()> {
-   let mut obfuscator = Obfuscator::new(
+   let obfuscator = Obfuscator::new(
       args.r#in,
       args.out
-   )?.with_map_file(args.map_file);
-   obfuscator.add_functions( args.functions)?;
+   )?;
+   let mut obfuscator = match (args.map_file, args.pdb) {
+      (_, Some(pdb)) => obfuscator.with_pdb(pdb),
+      (Some(map_file), _) => obfuscator.with_map_file(map_file),
+      _ => obfuscator,
+   };
+   obfuscator.use_pdata(!args.no_pdata);
+   for selector in args.exclude {
+      obfuscator.exclude(&selector)?;
+   }
+   for function in args.functions {
+      for symbol in obfuscator.add_function(function.clone())? {
+         println!("'{}' matched {} at {:#x}", function, symbol.name, symbol.rva);
+      }
+   }
+   for export in args.export {
+      obfuscator.add_export(export)?;
+   }
+   for (start, len) in args.rva_range {
+      obfuscator.add_rva_range(start, len)?;
+   }
+   obfuscator.add_marked_regions()?;
+   anyhow::ensure!(obfuscator.routines().next().is_some(), "nothing to virtualize, pass functions, exports, rva \
+      ranges or mark regions with the sdk");
+
+   for mismatch in obfuscator.bounds_mismatches() {
+      eprintln!("warning: {}", mismatch);
+   }
 
    obfuscator.virtualize()
 }
@@ -1 +1,4 @@
 pub mod parser;
+pub mod pdata;
+pub mod pdb_file;
+pub mod symbols;
@@ -33,6 +33,7 @@ pub enum LibObject {
 
 pub struct Function {
     pub symbol: String,
+    pub mangled: String,
     pub addr: Address,
     pub rva: Rva,
     pub flags: Vec<String>,
@@ -41,6 +42,7 @@ pub struct Function {
 
 pub struct StaticSymbol {
     pub symbol: String,
+    pub mangled: String,
     pub addr: Address,
     pub rva: Rva,
     pub flags: Vec<String>,
@@ -309,10 +311,12 @@ impl MapFile {
                         }
                     }
 
+                    let symbol = symbol.context("no symbol was found")?;
                     functions.push(Function {
                         addr: address.context("no address was found")?,
-                        symbol: symbolic_common::Name::from(symbol.context("no symbol was found")?).
+                        symbol: symbolic_common::Name::from(symbol).
                             try_demangle(symbolic_demangle::DemangleOptions::name_only()).to_string(),
+                        mangled: symbol.to_string(),
                         rva: rva.context("no rva was found")?,
                         flags,
                         libobj: libobj.context("no libobj was found")?,
@@ -421,10 +425,12 @@ impl MapFile {
                         }
                     }
 
+                    let symbol = symbol.context("no symbol was found")?;
                     static_symbols.push(StaticSymbol {
                         addr: address.context("no address was found")?,
-                        symbol: symbolic_common::Name::from(symbol.context("no symbol was found")?)
+                        symbol: symbolic_common::Name::from(symbol)
                             .try_demangle(symbolic_demangle::DemangleOptions::name_only()).to_string(),
+                        mangled: symbol.to_string(),
                         rva: rva.context("no rva was found")?,
                         flags,
                         libobj: libobj.context("no libobj was found")?,
@@ -445,42 +451,45 @@ impl MapFile {
     }
 
     pub fn get_function(&self, function_name: &str) -> Option<(Function, Size)> {
-        let mut found_function = None;
-        let mut size = 0;
+        self.get_functions(|name| name == function_name).into_iter().next()
+    }
+
+    /// All functions where `matches` accepts the demangled or the mangled name
+    pub fn get_functions(&self, matches: impl Fn(&str) -> bool) -> Vec<(Function, Size)> {
+        let mut found_functions = Vec::new();
 
         for function in &self.functions {
-            if function.flags.contains(&"f".to_string()) && function.symbol.eq(function_name) {
-                found_function = Some(Function {
+            if function.flags.contains(&"f".to_string()) && (matches(&function.symbol) || matches(&function.mangled)) {
+                found_functions.push(Function {
                     symbol: function.symbol.clone(),
+                    mangled: function.mangled.clone(),
                     addr: function.addr.clone(),
                     rva: function.rva.clone(),
                     flags: function.flags.clone(),
                     libobj: function.libobj.clone(),
                 });
-                break;
             }
-
         }
 
         for function in &self.static_symbols {
-            if function.flags.contains(&"f".to_string()) && function.symbol.eq(function_name) {
-                found_function = Some(Function {
+            if function.flags.contains(&"f".to_string()) && (matches(&function.symbol) || matches(&function.mangled)) {
+                found_functions.push(Function {
                     symbol: function.symbol.clone(),
+                    mangled: function.mangled.clone(),
                     addr: function.addr.clone(),
                     rva: function.rva.clone(),
                     flags: function.flags.clone(),
                     libobj: function.libobj.clone(),
                 });
-                break;
             }
-
-        }
-
-        if let Some(found_function) = &found_function {
-            size = self.find_next_function(found_function.rva.0) - found_function.rva.0;
         }
 
-        Some((found_function?, size))
+        found_functions.into_iter()
+            .map(|function| {
+                let size = self.find_next_function(function.rva.0) - function.rva.0;
+                (function, size)
+            })
+            .collect()
     }
 
     fn find_next_function(&self, rva: usize) -> usize {
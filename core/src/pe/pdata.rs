@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use exe::{Buffer, Error, ImageDirectoryEntry, PE, RVA, VecPE};
+
+// the unwind info continues the one of another runtime function, used for separated chunks
+const UNW_FLAG_CHAININFO: u8 = 0x4;
+
+/// RUNTIME_FUNCTION entry of the x64 exception directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuntimeFunction {
+    pub begin: u32,
+    pub end: u32,
+    pub unwind_info: u32,
+}
+
+impl RuntimeFunction {
+    const SIZE: usize = 12;
+
+    fn parse(data: &[u8]) -> Self {
+        let field = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+        Self { begin: field(0), end: field(1), unwind_info: field(2) }
+    }
+
+    pub fn range(&self) -> Range<u32> {
+        self.begin..self.end
+    }
+}
+
+/// Exact function bounds from the .pdata section. Leaf functions that never touch the stack have no
+/// entry, functions with separated chunks have one entry per chunk chained to the primary entry
+pub struct ExceptionTable {
+    functions: Vec<RuntimeFunction>,
+    // begin of each chained entry to the begin of its primary entry
+    chains: HashMap<u32, u32>,
+}
+
+impl ExceptionTable {
+    pub fn load(pe: &VecPE) -> Result<Self, Error> {
+        let directory = match pe.get_data_directory(ImageDirectoryEntry::Exception) {
+            Ok(directory) if directory.virtual_address.0 != 0 => *directory,
+            _ => return Ok(Self { functions: Vec::new(), chains: HashMap::new() }),
+        };
+
+        let offset = pe.rva_to_offset(directory.virtual_address)?;
+        let functions = pe.read(offset.into(), directory.size as usize)?
+            .chunks_exact(RuntimeFunction::SIZE)
+            .map(RuntimeFunction::parse)
+            .collect::<Vec<_>>();
+
+        let mut chains = HashMap::new();
+        for function in functions.iter() {
+            let mut primary = *function;
+            // chains are short, the limit only guards against malformed unwind info looping
+            for _ in 0..32 {
+                match Self::parent(pe, &primary) {
+                    Some(parent) => primary = parent,
+                    None => break,
+                }
+            }
+            if primary.begin != function.begin {
+                chains.insert(function.begin, primary.begin);
+            }
+        }
+
+        Ok(Self { functions, chains })
+    }
+
+    /// The runtime function the unwind info of `function` is chained to
+    fn parent(pe: &VecPE, function: &RuntimeFunction) -> Option<RuntimeFunction> {
+        let read = |rva: u32, len: usize| {
+            let offset = pe.rva_to_offset(RVA(rva)).ok()?;
+            pe.read(offset.into(), len).ok().map(RuntimeFunction::parse)
+        };
+
+        // the linker can point the unwind data straight at the parent, marked by the low bit
+        if function.unwind_info & 1 == 1 {
+            return read(function.unwind_info & !1, RuntimeFunction::SIZE);
+        }
+
+        let offset = pe.rva_to_offset(RVA(function.unwind_info)).ok()?;
+        let header = pe.read(offset.into(), 4).ok()?;
+        if header[0] >> 3 & UNW_FLAG_CHAININFO == 0 {
+            return None;
+        }
+        // the chained entry follows the unwind codes, which are padded to an even count
+        let codes = (header[2] as u32 + 1) & !1;
+        read(function.unwind_info + 4 + codes * 2, RuntimeFunction::SIZE)
+    }
+
+    /// The primary entry of the function starting at `rva`
+    pub fn function(&self, rva: u32) -> Option<&RuntimeFunction> {
+        self.functions.iter()
+            .find(|function| function.begin == rva && !self.chains.contains_key(&function.begin))
+    }
+
+    /// Start of the closest function or separated chunk after `rva`
+    pub fn next_function(&self, rva: u32) -> Option<u32> {
+        self.functions.iter()
+            .map(|function| function.begin)
+            .filter(|begin| *begin > rva)
+            .min()
+    }
+
+    /// All chunks of the function starting at `rva`, the primary one first and separated chunks sorted
+    /// by address. Empty if the function has no unwind info
+    pub fn chunks(&self, rva: u32) -> Vec<Range<u32>> {
+        let Some(primary) = self.function(rva) else {
+            return Vec::new();
+        };
+
+        let mut chunks = self.functions.iter()
+            .filter(|function| self.chains.get(&function.begin) == Some(&rva))
+            .map(RuntimeFunction::range)
+            .collect::<Vec<_>>();
+        chunks.sort_unstable_by_key(|chunk| chunk.start);
+        chunks.insert(0, primary.range());
+        chunks
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs::File;
+
+use anyhow::Result;
+use pdb::{FallibleIterator, PDB, SymbolData};
+use symbolic_demangle::{Demangle, DemangleOptions};
+
+use crate::pe::symbols::{FunctionSelector, FunctionSymbol, SymbolProvider};
+
+struct PdbFunction {
+    name: String,
+    mangled: Option<String>,
+    rva: u32,
+    // only procedures of modules with debug info have a size, plain public symbols don't
+    size: Option<usize>,
+}
+
+/// Functions from the public symbols and the global and private procedures of a pdb
+pub struct PdbFile {
+    functions: Vec<PdbFunction>,
+}
+
+impl PdbFile {
+    pub fn load(path: &str) -> Result<Self> {
+        let mut pdb = PDB::open(File::open(path)?)?;
+        let address_map = pdb.address_map()?;
+        let mut functions = Vec::new();
+
+        // procedures are only in the module streams, private ones never show up in the publics
+        let debug_information = pdb.debug_information()?;
+        let mut modules = debug_information.modules()?;
+        while let Some(module) = modules.next()? {
+            let Some(info) = pdb.module_info(&module)? else {
+                continue;
+            };
+            let mut symbols = info.symbols()?;
+            while let Some(symbol) = symbols.next()? {
+                if let Ok(SymbolData::Procedure(procedure)) = symbol.parse() {
+                    let Some(rva) = procedure.offset.to_rva(&address_map) else {
+                        continue;
+                    };
+                    functions.push(PdbFunction {
+                        name: procedure.name.to_string().into_owned(),
+                        mangled: None,
+                        rva: rva.0,
+                        size: Some(procedure.len as usize),
+                    });
+                }
+            }
+        }
+
+        let global_symbols = pdb.global_symbols()?;
+        let mut symbols = global_symbols.iter();
+        while let Some(symbol) = symbols.next()? {
+            let Ok(SymbolData::Public(public)) = symbol.parse() else {
+                continue;
+            };
+            let Some(rva) = public.offset.to_rva(&address_map).filter(|_| public.function) else {
+                continue;
+            };
+            let mangled = public.name.to_string().into_owned();
+
+            match functions.iter_mut().find(|function| function.rva == rva.0) {
+                Some(function) => function.mangled = Some(mangled),
+                None => functions.push(PdbFunction { name: demangle(&mangled), mangled: Some(mangled), rva: rva.0, size: None }),
+            }
+        }
+
+        Ok(Self { functions })
+    }
+
+    // same fallback as map files for functions without a procedure
+    fn find_next_function(&self, rva: u32) -> u32 {
+        self.functions.iter()
+            .map(|function| function.rva)
+            .filter(|next| *next > rva)
+            .min()
+            .unwrap_or(rva)
+    }
+
+    fn symbol(&self, function: &PdbFunction) -> FunctionSymbol {
+        FunctionSymbol {
+            name: function.name.clone(),
+            rva: function.rva,
+            size: function.size.unwrap_or_else(|| (self.find_next_function(function.rva) - function.rva) as usize),
+            exact: function.size.is_some(),
+        }
+    }
+}
+
+impl SymbolProvider for PdbFile {
+    /// Matches the procedure name, which is the rust path or the qualified c++ name, the demangled and the
+    /// mangled public symbol
+    fn function(&self, name: &str) -> Option<FunctionSymbol> {
+        let function = self.functions.iter().find(|function| function.name == name
+            || function.mangled.as_ref().is_some_and(|mangled| mangled == name || demangle(mangled) == name))?;
+        Some(self.symbol(function))
+    }
+
+    fn functions(&self, selector: &FunctionSelector) -> Vec<FunctionSymbol> {
+        let mut functions = selector.select(|matches| self.functions.iter()
+            .filter(|function| matches(&function.name) || function.mangled.as_ref()
+                .is_some_and(|mangled| matches(mangled) || matches(&demangle(mangled))))
+            .map(|function| self.symbol(function))
+            .collect());
+        functions.sort_unstable_by_key(|function| function.rva);
+        // folded functions share an address under several names
+        functions.dedup_by_key(|function| function.rva);
+        functions
+    }
+}
+
+fn demangle(symbol: &str) -> String {
+    symbolic_common::Name::from(symbol).try_demangle(DemangleOptions::name_only()).to_string()
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+
+use regex::Regex;
+
+use crate::pe::parser::MapFile;
+
+/// A function found by a [`SymbolProvider`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionSymbol {
+    pub name: String,
+    pub rva: u32,
+    pub size: usize,
+    /// The size is the code size from debug info rather than the distance to the next symbol, which
+    /// also covers padding
+    pub exact: bool,
+}
+
+/// Selects functions by name. `re:` starts a regex, names containing `*` or `?` are globs over the whole name
+/// or the end of the path after a `::`, e.g. `license::*` for `app::license::check`, where `*` also matches `::`,
+/// anything else has to match a name exactly. Globs only apply when no name equals the selector, mangled msvc
+/// names start with `?` and operators like `operator*` contain `*`. Every name of a function is tried, the
+/// demangled one and the mangled one if the symbols have it
+#[derive(Clone, Debug)]
+pub struct FunctionSelector {
+    selector: String,
+    pattern: Option<Regex>,
+}
+
+impl FunctionSelector {
+    pub fn parse(selector: &str) -> Result<Self, regex::Error> {
+        let pattern = match selector.strip_prefix("re:") {
+            Some(regex) => Some(Regex::new(regex)?),
+            None if selector.contains(['*', '?']) => {
+                let glob = selector.split('*')
+                    .map(|part| part.split('?').map(regex::escape).collect::<Vec<_>>().join("."))
+                    .collect::<Vec<_>>()
+                    .join(".*");
+                Some(Regex::new(&format!("^(?:.*::)?{glob}$"))?)
+            }
+            None => None,
+        };
+        Ok(Self { selector: selector.to_owned(), pattern })
+    }
+
+    /// Whether the glob or regex matches `name`, names equal to the selector are matched by [`Self::select`]
+    pub fn matches(&self, name: &str) -> bool {
+        self.pattern.as_ref().is_some_and(|pattern| pattern.is_match(name))
+    }
+
+    /// Runs `find` with a name predicate, first for names equal to the selector and for the glob or regex only
+    /// if that found nothing
+    pub fn select<T>(&self, find: impl Fn(&dyn Fn(&str) -> bool) -> Vec<T>) -> Vec<T> {
+        let exact = find(&|name| name == self.selector);
+        match exact.is_empty() {
+            true => find(&|name| self.matches(name)),
+            false => exact,
+        }
+    }
+}
+
+impl fmt::Display for FunctionSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.selector)
+    }
+}
+
+/// Source of function symbols used to locate the functions to virtualize
+pub trait SymbolProvider {
+    /// Finds a function by its demangled name, e.g. the fully qualified rust path `crate::module::function`
+    fn function(&self, name: &str) -> Option<FunctionSymbol>;
+
+    /// All functions matched by `selector` sorted by rva, e.g. every overload or generic instantiation
+    fn functions(&self, selector: &FunctionSelector) -> Vec<FunctionSymbol>;
+}
+
+impl SymbolProvider for MapFile {
+    fn function(&self, name: &str) -> Option<FunctionSymbol> {
+        let (function, size) = self.get_function(name)?;
+        Some(FunctionSymbol { name: function.symbol, rva: function.rva.0 as u32, size, exact: false })
+    }
+
+    fn functions(&self, selector: &FunctionSelector) -> Vec<FunctionSymbol> {
+        let mut functions = selector.select(|matches| self.get_functions(matches)).into_iter()
+            .map(|(function, size)| {
+                FunctionSymbol { name: function.symbol, rva: function.rva.0 as u32, size, exact: false }
+            })
+            .collect::<Vec<_>>();
+        functions.sort_unstable_by_key(|function| function.rva);
+        // folded functions share an address under several names
+        functions.dedup_by_key(|function| function.rva);
+        functions
+    }
+}
@@ -14,7 +14,7 @@ pub struct Machine {
     sp: *mut u64,
     pub regs: [u64; 16],
     pub fxsave: XSaveMin,
-    rflags: u64
+    pub rflags: u64
 }
 
 #[derive(Default)]
@@ -68,14 +68,42 @@ impl Assembler {
         self.emit_sized::<T>(Opcode::StoreRegZx);
     }
 
+    pub fn sign_extend<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::SignExtend);
+    }
+
     pub fn add<T: OpSized>(&mut self) {
         self.emit_sized::<T>(Opcode::Add);
     }
 
+    pub fn adc<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Adc);
+    }
+
     pub fn sub<T: OpSized>(&mut self) {
         self.emit_sized::<T>(Opcode::Sub);
     }
 
+    pub fn sbb<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Sbb);
+    }
+
+    pub fn neg<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Neg);
+    }
+
+    pub fn inc<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Inc);
+    }
+
+    pub fn dec<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Dec);
+    }
+
+    pub fn popf<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Popf);
+    }
+
     pub fn div<T: OpSized>(&mut self) {
         self.emit_sized::<T>(Opcode::Div);
     }
@@ -84,10 +112,18 @@ impl Assembler {
         self.emit_sized::<T>(Opcode::IDiv);
     }
 
+    pub fn shl<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Shl);
+    }
+
     pub fn shr<T: OpSized>(&mut self) {
         self.emit_sized::<T>(Opcode::Shr);
     }
 
+    pub fn sar<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Sar);
+    }
+
     pub fn combine<T: OpSized>(&mut self) {
         self.emit_sized::<T>(Opcode::Combine);
     }
@@ -100,6 +136,14 @@ impl Assembler {
         self.emit_sized::<T>(Opcode::Mul);
     }
 
+    pub fn imul<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::IMul);
+    }
+
+    pub fn imul_trunc<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::IMulTrunc);
+    }
+
     pub fn and<T: OpSized>(&mut self) {
         self.emit_sized::<T>(Opcode::And);
     }
@@ -120,6 +164,10 @@ impl Assembler {
         self.emit_sized::<T>(Opcode::Cmp);
     }
 
+    pub fn test<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Test);
+    }
+
     pub fn jmp(&mut self, cond: JmpCond, target: u64) {
         self.emit(Opcode::Jmp);
         self.emit_const::<u8>(cond as u8);
@@ -127,12 +175,101 @@ impl Assembler {
         self.emit_const::<u64>(target);
     }
 
-    pub fn rot_right(&mut self) {
-        self.emit_sized::<u16>(Opcode::RotR);
+    pub fn cond<T: OpSized>(&mut self, cond: JmpCond) {
+        self.emit_sized::<T>(Opcode::Cond);
+        self.emit_const::<u8>(cond as u8);
+    }
+
+    pub fn atomic<T: OpSized>(&mut self, op: AtomicOp) {
+        self.emit_sized::<T>(Opcode::Atomic);
+        self.emit_const::<u8>(op as u8);
+    }
+
+    pub fn string<T: OpSized>(&mut self, op: StringOp) {
+        self.emit_sized::<T>(Opcode::String);
+        self.emit_const::<u8>(op as u8);
+    }
+
+    pub fn packed<T: OpSized>(&mut self, op: PackedOp) {
+        self.emit_sized::<T>(Opcode::Packed);
+        self.emit_const::<u8>(op as u8);
+    }
+
+    pub fn pshufd(&mut self) {
+        self.emit(Opcode::Pshufd);
+    }
+
+    pub fn float<T: OpSized>(&mut self, op: FloatOp) {
+        self.emit_sized::<T>(Opcode::Float);
+        self.emit_const::<u8>(op as u8);
+    }
+
+    pub fn bt<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Bt);
+    }
+
+    pub fn bts<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Bts);
+    }
+
+    pub fn btr<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Btr);
+    }
+
+    pub fn btc<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Btc);
+    }
+
+    pub fn bit_addr<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::BitAddr);
+    }
+
+    pub fn bsf<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Bsf);
+    }
+
+    pub fn bsr<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Bsr);
+    }
+
+    pub fn tzcnt<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Tzcnt);
+    }
+
+    pub fn lzcnt<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Lzcnt);
+    }
+
+    pub fn popcnt<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Popcnt);
+    }
+
+    pub fn bswap<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Bswap);
     }
 
-    pub fn rot_left(&mut self) {
-        self.emit_sized::<u16>(Opcode::RotL);
+    pub fn rot_right<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::RotR);
+    }
+
+    pub fn rot_left<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::RotL);
+    }
+
+    pub fn rcr<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Rcr);
+    }
+
+    pub fn rcl<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Rcl);
+    }
+
+    pub fn shld<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Shld);
+    }
+
+    pub fn shrd<T: OpSized>(&mut self) {
+        self.emit_sized::<T>(Opcode::Shrd);
     }
 
     pub fn vmadd(&mut self) {
@@ -151,6 +288,14 @@ impl Assembler {
         self.emit(Opcode::Vmctx);
     }
 
+    pub fn gs_base(&mut self) {
+        self.emit(Opcode::GsBase);
+    }
+
+    pub fn fs_base(&mut self) {
+        self.emit(Opcode::FsBase);
+    }
+
     pub fn vmreloc(&mut self, image_base: u64) {
         self.emit(Opcode::VmReloc);
         self.emit_const::<u64>(image_base);
@@ -205,6 +350,15 @@ impl Assembler {
         ok()
     }
 
+    pub fn vmcall(&mut self, target: u64) {
+        self.emit(Opcode::VmCall);
+        self.emit_const::<u64>(target);
+    }
+
+    pub fn vmret(&mut self) {
+        self.emit(Opcode::VmRet);
+    }
+
     pub fn vmexit(&mut self) {
         self.emit(Opcode::VmExit);
     }
@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+
+use iced_x86::{Decoder, DecoderError, FlowControl, Instruction, Mnemonic};
+
+use crate::virtualizer::jump_table::JumpTable;
+
+/// Straight line code with a single entry, the last instruction is the only one that branches
+pub struct BasicBlock {
+    pub instructions: Vec<Instruction>,
+    /// Blocks of the same routine control can continue in, targets outside of it are tail jumps
+    pub successors: Vec<u64>,
+}
+
+impl BasicBlock {
+    pub fn start(&self) -> u64 {
+        self.instructions[0].ip()
+    }
+
+    pub fn end(&self) -> u64 {
+        self.instructions.last().unwrap().next_ip()
+    }
+}
+
+/// Control flow graph of a routine recovered by recursive descent from its entry, code is only decoded where
+/// control flow can actually reach it, padding and data in between the chunks' code is never lifted
+pub struct ControlFlowGraph {
+    /// Basic blocks by start address
+    pub blocks: BTreeMap<u64, BasicBlock>,
+    /// Branches leaving the routine as (branch ip, target), e.g. tail calls to other functions
+    pub tail_jumps: Vec<(u64, u64)>,
+    /// Bytes inside the chunks that aren't reachable code or padding and are likely read as data
+    pub data: Vec<Range<u64>>,
+    /// Jump tables recovered for indirect jmps by ip of the jmp
+    pub(crate) jump_tables: HashMap<u64, JumpTable>,
+}
+
+impl ControlFlowGraph {
+    /// Follows all branches from the entry of the first chunk. `read` reads bytes at an address and is used
+    /// for jump tables that aren't inside the chunks. Fails if control flow runs past the end of a chunk,
+    /// into the middle of an instruction or into bytes that don't decode.
+    pub fn recover(chunks: &[(u64, &[u8])], read: impl Fn(u64, usize) -> Option<Vec<u8>>) -> anyhow::Result<Self> {
+        let chunk = |address: u64| chunks.iter()
+            .find(|(ip, code)| (*ip..*ip + code.len() as u64).contains(&address));
+        let entry = chunks.first().map(|(ip, _)| *ip).unwrap_or_default();
+
+        let mut instructions = BTreeMap::<u64, Instruction>::new();
+        let mut successors = HashMap::<u64, Vec<u64>>::new();
+        let mut leaders = BTreeSet::from([entry]);
+        let mut tail_jumps = Vec::new();
+        let mut jump_tables = HashMap::new();
+
+        if chunks.first().is_none_or(|(_, code)| code.is_empty()) {
+            return Ok(Self { blocks: BTreeMap::new(), tail_jumps, data: Vec::new(), jump_tables });
+        }
+
+        // addresses to decode and the branch they are reached from
+        let mut pending = vec![(entry, None::<u64>)];
+        while let Some((start, source)) = pending.pop() {
+            if instructions.contains_key(&start) {
+                continue;
+            }
+            if let Some((_, overlapped)) = instructions.range(..start).next_back()
+                .filter(|(_, inst)| inst.next_ip() > start) {
+                anyhow::bail!("branch at {:#x} targets {start:#x} inside of the instruction at {:#x}",
+                    source.unwrap_or_default(), overlapped.ip());
+            }
+
+            let (ip, code) = *chunk(start).unwrap();
+            let end = ip + code.len() as u64;
+            let mut decoder = Decoder::with_ip(64, code, ip, 0);
+            decoder.set_position((start - ip) as usize)?;
+            decoder.set_ip(start);
+
+            loop {
+                // flowing into code that was already decoded from another branch
+                if instructions.contains_key(&decoder.ip()) {
+                    leaders.insert(decoder.ip());
+                    break;
+                }
+                if !decoder.can_decode() {
+                    anyhow::bail!("control flow runs past the end of the code at {end:#x}, the routine is cut short \
+                        or continues in a chunk that wasn't provided");
+                }
+
+                let inst = decoder.decode();
+                if inst.is_invalid() {
+                    match decoder.last_error() {
+                        DecoderError::NoMoreBytes => anyhow::bail!("the instruction at {:#x} runs past the end of \
+                            the code at {end:#x}", inst.ip()),
+                        _ => anyhow::bail!("invalid instruction at {:#x}, control flow reaches data", inst.ip()),
+                    }
+                }
+                instructions.insert(inst.ip(), inst);
+
+                let (targets, falls_through) = match inst.flow_control() {
+                    FlowControl::Next | FlowControl::Call | FlowControl::IndirectCall
+                    | FlowControl::XbeginXabortXend => continue,
+                    FlowControl::ConditionalBranch => (vec![inst.near_branch_target()], true),
+                    FlowControl::UnconditionalBranch if inst.is_jmp_short_or_near() => {
+                        (vec![inst.near_branch_target()], false)
+                    }
+                    FlowControl::IndirectBranch => {
+                        let history = instructions.range(ip..inst.ip()).map(|(_, inst)| *inst).collect::<Vec<_>>();
+                        match JumpTable::recover(&history, &inst, &read, ip..end) {
+                            Some(table) => {
+                                // cases outside the routine are left to the native jmp
+                                let targets = table.targets.iter().copied()
+                                    .filter(|target| chunk(*target).is_some())
+                                    .collect();
+                                jump_tables.insert(inst.ip(), table);
+                                (targets, false)
+                            }
+                            None => (Vec::new(), false),
+                        }
+                    }
+                    // ret, far jmps, int3 after calls that don't return and ud2
+                    _ => (Vec::new(), false),
+                };
+
+                for target in targets {
+                    if chunk(target).is_some() {
+                        leaders.insert(target);
+                        pending.push((target, Some(inst.ip())));
+                        successors.entry(inst.ip()).or_default().push(target);
+                    } else {
+                        tail_jumps.push((inst.ip(), target));
+                    }
+                }
+
+                if !falls_through {
+                    break;
+                }
+                leaders.insert(inst.next_ip());
+                successors.entry(inst.ip()).or_default().push(inst.next_ip());
+            }
+        }
+
+        let mut blocks = BTreeMap::<u64, BasicBlock>::new();
+        let mut current = None::<BasicBlock>;
+        for (ip, inst) in instructions.iter() {
+            // a branch target splits the block that falls through into it
+            if leaders.contains(ip) {
+                if let Some(mut block) = current.take() {
+                    block.successors.push(*ip);
+                    blocks.insert(block.start(), block);
+                }
+            }
+
+            let block = current.get_or_insert_with(|| BasicBlock { instructions: Vec::new(), successors: Vec::new() });
+            block.instructions.push(*inst);
+            if !matches!(inst.flow_control(), FlowControl::Next | FlowControl::Call | FlowControl::IndirectCall
+                | FlowControl::XbeginXabortXend) {
+                block.successors = successors.remove(ip).unwrap_or_default();
+                blocks.insert(block.start(), current.take().unwrap());
+            }
+        }
+
+        let mut data = jump_tables.values()
+            .map(|table| table.table.clone())
+            .filter(|table| chunk(table.start).is_some())
+            .collect::<Vec<_>>();
+        for &(ip, code) in chunks {
+            let mut gap = ip;
+            let ends = blocks.range(ip..ip + code.len() as u64)
+                .map(|(start, block)| (*start, block.end()))
+                .chain(std::iter::once((ip + code.len() as u64, ip + code.len() as u64)));
+            for (start, end) in ends {
+                if start > gap && !data.iter().any(|data| data.contains(&gap))
+                    && !Self::is_padding(gap, &code[(gap - ip) as usize..(start - ip) as usize]) {
+                    data.push(gap..start);
+                }
+                gap = gap.max(end);
+            }
+        }
+
+        Ok(Self { blocks, tail_jumps, data, jump_tables })
+    }
+
+    /// Int3 and nops the compiler fills alignment gaps with
+    fn is_padding(ip: u64, code: &[u8]) -> bool {
+        let mut decoder = Decoder::with_ip(64, code, ip, 0);
+        decoder.iter().all(|inst| matches!(inst.mnemonic(), Mnemonic::Int3 | Mnemonic::Nop))
+    }
+
+    /// Whether control flow inside the routine can reach `address`, i.e. a branch to it stays in the routine
+    pub fn contains(&self, address: u64) -> bool {
+        self.blocks.contains_key(&address)
+    }
+
+    /// Whether ret is the only way out of the routine, no tail jumps, unknown indirect jmps or jump table
+    /// cases leave it. Only these can be called inside the vm, any other exit would return natively to the
+    /// tagged return address of the vm call
+    pub fn returns_only(&self) -> bool {
+        self.tail_jumps.is_empty() && self.blocks.values().all(|block| {
+            let last = block.instructions.last().unwrap();
+            last.flow_control() != FlowControl::IndirectBranch || self.jump_tables.get(&last.ip())
+                .is_some_and(|table| table.targets.iter().all(|target| self.contains(*target)))
+        })
+    }
+
+    /// All reachable instructions in address order, blocks that fall through are contiguous
+    pub fn instructions(&self) -> impl Iterator<Item = &Instruction> {
+        self.blocks.values().flat_map(|block| block.instructions.iter())
+    }
+}
@@ -11,6 +11,10 @@ struct Instruction {
     op_code: Opcode,
     op_size: OpSize,
     jmp_cond: Option<JmpCond>,
+    atomic_op: Option<AtomicOp>,
+    string_op: Option<StringOp>,
+    packed_op: Option<PackedOp>,
+    float_op: Option<FloatOp>,
     instr_size: Option<u8>,
     instr: Option<Vec<u8>>,
     // some dont have size encoded
@@ -31,9 +35,16 @@ impl Instruction {
         let mut instr = Self {
             op_code,
             op_size,
-            jmp_cond: op_code
-                .eq(&Opcode::Jmp)
+            jmp_cond: matches!(op_code, Opcode::Jmp | Opcode::Cond)
                 .then(|| unsafe { JmpCond::try_from(instr_ptr.add(2).read_unaligned()).unwrap() }),
+            atomic_op: op_code.eq(&Opcode::Atomic)
+                .then(|| unsafe { AtomicOp::try_from(instr_ptr.add(2).read_unaligned()).unwrap() }),
+            string_op: op_code.eq(&Opcode::String)
+                .then(|| unsafe { StringOp::try_from(instr_ptr.add(2).read_unaligned()).unwrap() }),
+            packed_op: op_code.eq(&Opcode::Packed)
+                .then(|| unsafe { PackedOp::try_from(instr_ptr.add(2).read_unaligned()).unwrap() }),
+            float_op: op_code.eq(&Opcode::Float)
+                .then(|| unsafe { FloatOp::try_from(instr_ptr.add(2).read_unaligned()).unwrap() }),
             instr_size,
             instr: op_code.eq(&Opcode::VmExec).then(|| unsafe {
                 let instr_size = instr_size.unwrap() as usize;
@@ -51,7 +62,7 @@ impl Instruction {
 
     unsafe fn read_value(&self, instr_ptr: *const u8) -> Option<u64> {
         let val_ptr = match self.op_code {
-            Opcode::Const | Opcode::VmReloc => instr_ptr.add(2),
+            Opcode::Const | Opcode::VmReloc | Opcode::VmCall => instr_ptr.add(2),
             Opcode::Jmp => instr_ptr.add(3),
             _ => None?,
         };
@@ -78,11 +89,16 @@ impl Instruction {
                 buffer.push(self.jmp_cond.clone().unwrap() as u8);
                 buffer.extend_from_slice(&self.value.unwrap().to_le_bytes());
             }
+            Opcode::Cond => buffer.push(self.jmp_cond.clone().unwrap() as u8),
+            Opcode::Atomic => buffer.push(self.atomic_op.unwrap() as u8),
+            Opcode::String => buffer.push(self.string_op.unwrap() as u8),
+            Opcode::Packed => buffer.push(self.packed_op.unwrap() as u8),
+            Opcode::Float => buffer.push(self.float_op.unwrap() as u8),
             Opcode::VmExec => {
                 buffer.push(self.instr_size.unwrap());
                 buffer.extend_from_slice(self.instr.as_ref().unwrap());
             }
-            Opcode::Const | Opcode::VmReloc => {
+            Opcode::Const | Opcode::VmReloc | Opcode::VmCall => {
                 let value = self.value.unwrap();
                 match self.op_size {
                     OpSize::Byte => buffer.extend_from_slice(&(value as u8).to_le_bytes()),
@@ -98,10 +114,15 @@ impl Instruction {
     pub fn length(&self) -> usize {
         let mut length = 2; // opcode + opsize
         length += match self.op_code {
-            Opcode::Const | Opcode::VmReloc => self.op_size as u8 as usize,
+            Opcode::Const | Opcode::VmReloc | Opcode::VmCall => self.op_size as u8 as usize,
             Opcode::Jmp => {
                 self.op_size as u8 as usize + 1 // jmp cond
             }
+            Opcode::Cond => 1, // jmp cond
+            Opcode::Atomic => 1, // atomic op
+            Opcode::String => 1, // string op
+            Opcode::Packed => 1, // packed op
+            Opcode::Float => 1, // float op
             Opcode::VmExec => {
                 self.instr_size.unwrap() as usize + 1 // instr_size
             }
@@ -124,28 +145,65 @@ impl Opcode {
             Opcode::StoreXmm => export_map.get("store_xmm_handler")?,
             Opcode::StoreReg => export_map.get("store_reg_handler")?,
             Opcode::StoreRegZx => export_map.get("store_reg_zx_handler")?,
+            Opcode::SignExtend => export_map.get("sign_extend_handler")?,
             Opcode::Add => export_map.get("add_handler")?,
+            Opcode::Adc => export_map.get("adc_handler")?,
             Opcode::Sub => export_map.get("sub_handler")?,
+            Opcode::Sbb => export_map.get("sbb_handler")?,
+            Opcode::Neg => export_map.get("neg_handler")?,
+            Opcode::Inc => export_map.get("inc_handler")?,
+            Opcode::Dec => export_map.get("dec_handler")?,
+            Opcode::Popf => export_map.get("popf_handler")?,
             Opcode::Div => export_map.get("div_handler")?,
             Opcode::IDiv => export_map.get("idiv_handler")?,
+            Opcode::Shl => export_map.get("shl_handler")?,
             Opcode::Shr => export_map.get("shr_handler")?,
+            Opcode::Sar => export_map.get("sar_handler")?,
             Opcode::Combine => export_map.get("combine_handler")?,
             Opcode::Split => export_map.get("split_handler")?,
             Opcode::Mul => export_map.get("mul_handler")?,
+            Opcode::IMul => export_map.get("imul_handler")?,
+            Opcode::IMulTrunc => export_map.get("imul_trunc_handler")?,
             Opcode::And => export_map.get("and_handler")?,
             Opcode::Or => export_map.get("or_handler")?,
             Opcode::Xor => export_map.get("xor_handler")?,
             Opcode::Not => export_map.get("not_handler")?,
             Opcode::Cmp => export_map.get("cmp_handler")?,
+            Opcode::Test => export_map.get("test_handler")?,
+            Opcode::Atomic => export_map.get("atomic_handler")?,
+            Opcode::String => export_map.get("string_handler")?,
+            Opcode::Packed => export_map.get("packed_handler")?,
+            Opcode::Pshufd => export_map.get("pshufd_handler")?,
+            Opcode::Float => export_map.get("float_handler")?,
+            Opcode::Bt => export_map.get("bt_handler")?,
+            Opcode::Bts => export_map.get("bts_handler")?,
+            Opcode::Btr => export_map.get("btr_handler")?,
+            Opcode::Btc => export_map.get("btc_handler")?,
+            Opcode::BitAddr => export_map.get("bit_addr_handler")?,
+            Opcode::Bsf => export_map.get("bsf_handler")?,
+            Opcode::Bsr => export_map.get("bsr_handler")?,
+            Opcode::Tzcnt => export_map.get("tzcnt_handler")?,
+            Opcode::Lzcnt => export_map.get("lzcnt_handler")?,
+            Opcode::Popcnt => export_map.get("popcnt_handler")?,
+            Opcode::Bswap => export_map.get("bswap_handler")?,
             Opcode::RotR => export_map.get("rot_r_handler")?,
             Opcode::RotL => export_map.get("rot_l_handler")?,
+            Opcode::Rcr => export_map.get("rcr_handler")?,
+            Opcode::Rcl => export_map.get("rcl_handler")?,
+            Opcode::Shld => export_map.get("shld_handler")?,
+            Opcode::Shrd => export_map.get("shrd_handler")?,
             Opcode::Jmp => export_map.get("jmp_handler")?,
+            Opcode::Cond => export_map.get("cond_handler")?,
             Opcode::Vmctx => export_map.get("vm_ctx_handler")?,
+            Opcode::GsBase => export_map.get("gs_base_handler")?,
+            Opcode::FsBase => export_map.get("fs_base_handler")?,
             Opcode::VmAdd => export_map.get("vm_add_handler")?,
             Opcode::VmMul => export_map.get("vm_mul_handler")?,
             Opcode::VmSub => export_map.get("vm_sub_handler")?,
             Opcode::VmReloc => export_map.get("vm_reloc_handler")?,
             Opcode::VmExec => export_map.get("vm_exec_handler")?,
+            Opcode::VmCall => export_map.get("vm_call_handler")?,
+            Opcode::VmRet => export_map.get("vm_ret_handler")?,
             Opcode::VmExit => export_map.get("vmexit_threaded")?,
         };
 
@@ -156,7 +214,10 @@ impl Opcode {
     }
 }
 
-pub fn convert_to_threaded_code(vm: &VecPE, vm_section: RVA, program: &[u8]) -> anyhow::Result<Vec<u8>> {
+/// Converts bytecode to threaded code, `entries` are offsets of routines in `program` which are returned
+/// translated to offsets in the threaded code
+pub fn convert_to_threaded_code(vm: &VecPE, vm_section: RVA, program: &[u8], entries: &[usize])
+    -> anyhow::Result<(Vec<u8>, Vec<usize>)> {
     let mut offset_map = HashMap::<usize, usize>::new();
     let mut new_offset_map = HashMap::<usize, usize>::new();
     let mut pc = program.as_ptr();
@@ -213,6 +274,23 @@ pub fn convert_to_threaded_code(vm: &VecPE, vm_section: RVA, program: &[u8]) ->
                 index += 10;
                 pc = unsafe { pc.add(8) };
                 continue;
+            } else if op_code == Opcode::VmCall {
+                // skip op_size
+                pc = unsafe { pc.add(1) };
+
+                let call_target = unsafe { pc.cast::<i64>().read_unaligned() };
+                let call_offset = (*old_offset as i64).wrapping_sub(call_target) as usize;
+                let new_offset = *new_offset_map.get(&call_offset)
+                    .ok_or(anyhow!("couldn't translate call_offset"))?;
+
+                unsafe {
+                    let call_addr = pc.cast_mut().cast::<u64>();
+                    call_addr.write_unaligned(index.wrapping_sub(new_offset) as u64);
+                }
+                // op_size u8, call_target u64 = 9
+                index += 9;
+                pc = unsafe { pc.add(8) };
+                continue;
             }
         }
 
@@ -220,7 +298,13 @@ pub fn convert_to_threaded_code(vm: &VecPE, vm_section: RVA, program: &[u8]) ->
         pc = unsafe { pc.add(1) };
     }
 
-    Ok(obfuscated)
+    // entries start at the next handler of the previous instruction
+    let entries = entries.iter()
+        .map(|entry| new_offset_map.get(entry).map(|offset| offset - 8)
+            .ok_or(anyhow!("couldn't translate entry")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok((obfuscated, entries))
 }
 
 pub fn disassemble(program: &[u8]) -> Result<String> {
@@ -249,7 +333,7 @@ pub fn disassemble(program: &[u8]) -> Result<String> {
 
         #[allow(clippy::single_match)]
         match instruction.op_code {
-            Opcode::Const | Opcode::VmReloc => 'label: {
+            Opcode::Const | Opcode::VmReloc | Opcode::VmCall => 'label: {
                 //let v = *(pc as *const u64);
                 let value = instruction.value.unwrap();
 
@@ -269,6 +353,21 @@ pub fn disassemble(program: &[u8]) -> Result<String> {
                 let val = instruction.value.unwrap();
                 s.push_str(format!(" {:?} 0x{:x}", cond, val).as_str());
             },
+            Opcode::Cond => {
+                s.push_str(format!(" {:?}", instruction.jmp_cond.clone().unwrap()).as_str());
+            }
+            Opcode::Atomic => {
+                s.push_str(format!(" {:?}", instruction.atomic_op.unwrap()).as_str());
+            }
+            Opcode::String => {
+                s.push_str(format!(" {:?}", instruction.string_op.unwrap()).as_str());
+            }
+            Opcode::Packed => {
+                s.push_str(format!(" {:?}", instruction.packed_op.unwrap()).as_str());
+            }
+            Opcode::Float => {
+                s.push_str(format!(" {:?}", instruction.float_op.unwrap()).as_str());
+            }
             Opcode::VmExec => {
                 /* todo
                 let instr_size = pc.add(2).read_unaligned() as usize;
@@ -0,0 +1,116 @@
+use std::ops::Range;
+
+use iced_x86::{Instruction, InstructionInfoFactory, Mnemonic, OpAccess, OpKind, Register};
+
+// anything bigger is most likely not a jump table
+const MAX_ENTRIES: usize = 0x400;
+
+/// A switch jump table recovered from the `lea base, [table]; movsxd target, [base + idx*4]; add target, base; jmp target`
+/// pattern. Msvc emits the same shape with zero extended rvas, `mov target32, [base + idx*4 + table]` with base being the
+/// image base.
+pub struct JumpTable {
+    /// Address range of the table itself
+    pub table: Range<u64>,
+    pub targets: Vec<u64>,
+}
+
+impl JumpTable {
+    /// `history` are the instructions decoded before `jmp`, `read` reads bytes at an address and `bounds` is the
+    /// address range of the function, only used to size tables that aren't bounds checked.
+    pub fn recover(
+        history: &[Instruction],
+        jmp: &Instruction,
+        read: impl Fn(u64, usize) -> Option<Vec<u8>>,
+        bounds: Range<u64>,
+    ) -> Option<Self> {
+        if jmp.op0_kind() != OpKind::Register {
+            return None;
+        }
+        let target = jmp.op0_register();
+        let mut factory = InstructionInfoFactory::new();
+        let mut writes = |inst: &Instruction, reg: Register| factory.info(inst).used_registers().iter()
+            .any(|used| used.register().full_register() == reg && used.access() != OpAccess::Read
+                && used.access() != OpAccess::CondRead);
+
+        let last_write = |history: &[Instruction], writes: &mut dyn FnMut(&Instruction, Register) -> bool, reg: Register| {
+            history.iter().rposition(|inst| writes(inst, reg))
+        };
+
+        // add target, base
+        let add_index = last_write(history, &mut writes, target)?;
+        let add = &history[add_index];
+        if add.mnemonic() != Mnemonic::Add || add.op0_register() != target || add.op1_kind() != OpKind::Register {
+            return None;
+        }
+        let base = add.op1_register();
+
+        // movsxd target, [base + idx*4] or mov target32, [base + idx*4 + table]
+        let load_index = last_write(&history[..add_index], &mut writes, target)?;
+        let load = &history[load_index];
+        let signed = match load.mnemonic() {
+            Mnemonic::Movsxd => true,
+            Mnemonic::Mov if load.op0_register().is_gpr32() => false,
+            _ => return None,
+        };
+        if load.op0_register().full_register() != target || load.op1_kind() != OpKind::Memory
+            || load.memory_base() != base || load.memory_index_scale() != 4
+            || history[load_index + 1..add_index].iter().any(|inst| writes(inst, base)) {
+            return None;
+        }
+        let index = load.memory_index().full_register();
+
+        // lea base, [rip + x]
+        let lea_index = last_write(&history[..load_index], &mut writes, base)?;
+        let lea = &history[lea_index];
+        if lea.mnemonic() != Mnemonic::Lea || lea.memory_base() != Register::RIP {
+            return None;
+        }
+        let base_address = lea.memory_displacement64();
+        let table = base_address.wrapping_add(load.memory_displacement64());
+
+        // cmp idx, n; ja default
+        let entries = history[..load_index].windows(2).rev()
+            .take_while(|pair| !writes(&pair[1], index))
+            .find_map(|pair| {
+                let (cmp, jcc) = (&pair[0], &pair[1]);
+                let checked = cmp.mnemonic() == Mnemonic::Cmp && cmp.op0_kind() == OpKind::Register
+                    && cmp.op0_register().full_register() == index;
+                match jcc.mnemonic() {
+                    Mnemonic::Ja if checked => Some(cmp.immediate(1) as usize + 1),
+                    Mnemonic::Jae if checked => Some(cmp.immediate(1) as usize),
+                    _ => None,
+                }
+            });
+
+        let entry_target = |entry: &[u8]| {
+            let entry = u32::from_le_bytes(entry.try_into().unwrap());
+            match signed {
+                true => base_address.wrapping_add(entry as i32 as i64 as u64),
+                false => base_address.wrapping_add(entry as u64),
+            }
+        };
+
+        let targets = match entries {
+            Some(entries) if entries <= MAX_ENTRIES => read(table, entries * 4)?
+                .chunks(4)
+                .map(entry_target)
+                .collect::<Vec<_>>(),
+            Some(_) => return None,
+            // without a bounds check take entries until one leaves the function
+            None => (0..MAX_ENTRIES)
+                .map_while(|i| read(table + i as u64 * 4, 4))
+                .map(|entry| entry_target(&entry))
+                .take_while(|target| bounds.contains(target))
+                .collect::<Vec<_>>(),
+        };
+
+        if targets.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            table: table..table + targets.len() as u64 * 4,
+            targets,
+        })
+    }
+}
@@ -1,43 +1,87 @@
-use std::collections::HashMap;
-use exe::{PE, VecPE};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use anyhow::Context;
+use exe::{Buffer, PE, RVA, VecPE};
 
-use iced_x86::{Decoder, Formatter, Instruction, Mnemonic, NasmFormatter, OpKind};
+use iced_x86::{FlowControl, Formatter, Instruction, Mnemonic, NasmFormatter, OpKind};
+use memoffset::offset_of;
 
 use traits::*;
 
-use crate::shared::{JmpCond, OpSize};
-use crate::virtualizer::assembler::Assembler;
+use crate::shared::{AtomicOp, JmpCond, OpSize, FloatOp, PackedOp, StringOp, VIRTUAL_RETURN};
+use crate::virtualizer::assembler::{Assembler, Machine};
+use crate::virtualizer::cfg::ControlFlowGraph;
+use crate::virtualizer::jump_table::JumpTable;
 
 pub mod assembler;
+pub mod cfg;
 pub mod disassembler;
+mod jump_table;
 mod traits;
 
 trait Asm {
     fn const_<T: OpSized>(&mut self, v: T);
     fn load<T: OpSized>(&mut self);
     fn store<T: OpSized>(&mut self);
+    fn sign_extend<T: OpSized>(&mut self);
     fn add<T: OpSized>(&mut self);
+    fn adc<T: OpSized>(&mut self);
     fn sub<T: OpSized>(&mut self);
+    fn sbb<T: OpSized>(&mut self);
+    fn neg<T: OpSized>(&mut self);
+    fn inc<T: OpSized>(&mut self);
+    fn dec<T: OpSized>(&mut self);
+    fn popf<T: OpSized>(&mut self);
     fn div<T: OpSized>(&mut self, signed: bool);
+    fn shl<T: OpSized>(&mut self);
     fn shr<T: OpSized>(&mut self);
+    fn sar<T: OpSized>(&mut self);
+    fn rot_left<T: OpSized>(&mut self);
+    fn rot_right<T: OpSized>(&mut self);
+    fn rcl<T: OpSized>(&mut self);
+    fn rcr<T: OpSized>(&mut self);
+    fn shld<T: OpSized>(&mut self);
+    fn shrd<T: OpSized>(&mut self);
     fn combine<T: OpSized>(&mut self);
     fn split<T: OpSized>(&mut self);
-    fn mul<T: OpSized>(&mut self);
+    fn mul<T: OpSized>(&mut self, signed: bool);
+    fn imul_trunc<T: OpSized>(&mut self);
     fn and<T: OpSized>(&mut self);
     fn or<T: OpSized>(&mut self);
     fn xor<T: OpSized>(&mut self);
     fn not<T: OpSized>(&mut self);
     fn cmp<T: OpSized>(&mut self);
+    fn test<T: OpSized>(&mut self);
+    fn cond<T: OpSized>(&mut self, cond: JmpCond);
+    fn atomic<T: OpSized>(&mut self, op: AtomicOp);
+    fn string<T: OpSized>(&mut self, op: StringOp);
+    fn packed<T: OpSized>(&mut self, op: PackedOp);
+    fn pshufd(&mut self);
+    fn float<T: OpSized>(&mut self, op: FloatOp);
+    fn bt<T: OpSized>(&mut self);
+    fn bts<T: OpSized>(&mut self);
+    fn btr<T: OpSized>(&mut self);
+    fn btc<T: OpSized>(&mut self);
+    fn bit_addr<T: OpSized>(&mut self);
+    fn bsf<T: OpSized>(&mut self);
+    fn bsr<T: OpSized>(&mut self);
+    fn tzcnt<T: OpSized>(&mut self);
+    fn lzcnt<T: OpSized>(&mut self);
+    fn popcnt<T: OpSized>(&mut self);
+    fn bswap<T: OpSized>(&mut self);
     fn vmadd(&mut self);
     fn vmsub(&mut self);
     fn vmmul(&mut self);
     fn vmctx(&mut self);
     fn vmexit(&mut self);
+    fn vmret(&mut self);
     fn load_operand(&mut self, inst: &Instruction, operand: u32);
     fn store_operand(&mut self, inst: &Instruction, operand: u32);
     fn load_reg(&mut self, reg: iced_x86::Register);
     fn store_reg(&mut self, reg: iced_x86::Register);
     fn store_reg_zx(&mut self, inst: &Instruction, reg: iced_x86::Register);
+    fn load_rflags(&mut self);
+    fn store_rflags(&mut self);
     fn lea_operand(&mut self, inst: &Instruction);
 }
 
@@ -106,6 +150,14 @@ pub struct Virtualizer {
     asm: Assembler,
     pe: Option<VecPE>,
     image_base: u64,
+    // data embedded in the virtualized code that is still read at runtime, e.g. jump tables
+    data: Vec<Range<u64>>,
+    // entries of the virtualized routines that only leave through ret, calls to them stay inside the vm
+    routines: HashSet<u64>,
+    // offset of the vmcall operand and the called routine
+    calls: Vec<(usize, u64)>,
+    // fs segment operands are lifted on top of rdfsbase instead of executed natively
+    fsgsbase: bool,
 }
 
 impl Default for Virtualizer {
@@ -120,6 +172,10 @@ impl Virtualizer {
             asm: Assembler::default(),
             pe: None,
             image_base: 0,
+            data: Vec::new(),
+            routines: HashSet::new(),
+            calls: Vec::new(),
+            fsgsbase: false,
         }
     }
 
@@ -128,11 +184,78 @@ impl Virtualizer {
             asm: Assembler::default(),
             image_base: pe.get_image_base()?,
             pe: Some(pe),
+            data: Vec::new(),
+            routines: HashSet::new(),
+            calls: Vec::new(),
+            fsgsbase: false,
         })
     }
 
+    /// Image base native calls are relocated against when there is no pe, e.g. for code of the running module
+    pub fn with_image_base(mut self, image_base: u64) -> Self {
+        self.image_base = image_base;
+        self
+    }
+
+    /// Lift fs segment operands by reading the fs base with rdfsbase. Only for targets whose os enables
+    /// FSGSBASE for user mode, otherwise instructions using fs are executed natively with vmexec
+    pub fn with_fsgsbase(mut self, enable: bool) -> Self {
+        self.fsgsbase = enable;
+        self
+    }
+
     pub fn reset(&mut self) {
         self.asm.clear();
+        self.data.clear();
+        self.calls.clear();
+    }
+
+    /// Address ranges inside the virtualized code that are data read at runtime and have to be preserved
+    pub fn data_ranges(&self) -> &[Range<u64>] {
+        &self.data
+    }
+
+    /// Virtualizes routines given as (ip, code) into one bytecode buffer, calls to the routines that only
+    /// leave through ret are linked to vm calls. Returns the bytecode and the offset of each routine in it.
+    pub fn virtualize_routines(&mut self, routines: &[(u64, &[u8])]) -> anyhow::Result<(Vec<u8>, Vec<usize>)> {
+        let routines = routines.iter().map(|routine| vec![*routine]).collect::<Vec<_>>();
+        self.virtualize_chunked_routines(&routines)
+    }
+
+    /// Same as [`Self::virtualize_routines`] for routines split into chunks, see [`Self::virtualize_chunks`]
+    pub fn virtualize_chunked_routines(&mut self, routines: &[Vec<(u64, &[u8])>]) -> anyhow::Result<(Vec<u8>, Vec<usize>)> {
+        // routines that can leave the vm other than by ret are still called natively
+        let mut callable = HashSet::new();
+        for chunks in routines.iter().filter(|chunks| !chunks.is_empty()) {
+            let cfg = ControlFlowGraph::recover(chunks, |address, len| self.read(chunks, address, len))
+                .with_context(|| format!("failed to virtualize the routine at {:#x}", chunks[0].0))?;
+            if cfg.returns_only() {
+                callable.insert(chunks[0].0);
+            }
+        }
+        self.routines = callable;
+
+        let mut bytecode = Vec::new();
+        let mut entries = Vec::new();
+        let mut calls = Vec::new();
+
+        for chunks in routines {
+            entries.push(bytecode.len());
+            let mut virtualized = self.virtualize_chunks(chunks)
+                .with_context(|| format!("failed to virtualize the routine at {:#x}", chunks[0].0))?;
+            calls.extend(self.calls.drain(..).map(|(offset, target)| (bytecode.len() + offset, target)));
+            bytecode.append(&mut virtualized);
+            self.asm.clear();
+        }
+
+        for (offset, target) in calls {
+            let routine = routines.iter().position(|chunks| chunks[0].0 == target).unwrap();
+            // relative to the vmcall instruction like jmps
+            let call = (offset - 2) as u64;
+            bytecode[offset..][..8].copy_from_slice(&call.wrapping_sub(entries[routine] as u64).to_le_bytes());
+        }
+
+        Ok((bytecode, entries))
     }
 
     pub fn virtualize(&mut self, program: &[u8]) -> anyhow::Result<Vec<u8>> {
@@ -140,41 +263,145 @@ impl Virtualizer {
     }
 
     pub fn virtualize_with_ip(&mut self, ip: u64, program: &[u8]) -> anyhow::Result<Vec<u8>> {
-        let mut decoder = Decoder::with_ip(64, program, ip, 0);
+        self.virtualize_chunks(&[(ip, program)])
+    }
+
+    /// Virtualizes a routine that is split into chunks given as (ip, code), e.g. a function with its
+    /// cold code moved out of line. The first chunk holds the entry, jmps between chunks stay in the vm
+    pub fn virtualize_chunks(&mut self, chunks: &[(u64, &[u8])]) -> anyhow::Result<Vec<u8>> {
+        let entry = chunks.first().map(|(ip, _)| *ip).unwrap_or_default();
         let mut unresolved_jmps = 0;
         // maps buffer offset (jmp) to ip
         let mut jmp_map = HashMap::<u64, u64>::new();
         // maps ip to buffer offset
         let mut target_map = HashMap::<u64, u64>::new();
 
-        for inst in decoder.iter() {
+        let cfg = ControlFlowGraph::recover(chunks, |address, len| self.read(chunks, address, len))?;
+        self.data.extend(cfg.data.iter().cloned());
+        // conditional tail jumps, they leave the vm through an exit emitted after the routine
+        let mut tail_jmps = Vec::<(u64, u64)>::new();
+
+        // blocks are lifted in address order for their fall throughs, a chunk below the entry would come
+        // first and the routine has to start with a jmp to the entry
+        if cfg.blocks.keys().next().is_some_and(|start| *start != entry) {
+            jmp_map.insert(self.asm.len() as u64, entry);
+            self.asm.jmp(JmpCond::Jmp, 0);
+            unresolved_jmps += 1;
+        }
+
+        for inst in cfg.instructions() {
+            let inst = *inst;
             target_map.insert(inst.ip(), self.asm.len() as u64);
 
             match inst.mnemonic() {
+                // without fsgsbase the vm can't read the fs base, so fs accesses are executed natively.
+                // Branches through fs would leave the vm for good and are rejected
+                _ if inst.segment_prefix() == iced_x86::Register::FS && !self.fsgsbase => {
+                    if inst.flow_control() != FlowControl::Next {
+                        let mut output = String::new();
+                        NasmFormatter::new().format(&inst, &mut output);
+                        anyhow::bail!("unsupported jmp: {}", output);
+                    }
+                    self.asm.vmexec(inst, self.pe.as_ref(), self.image_base)?;
+                }
+                // locked instructions without a handler are the only ones passed through with vmexec
+                _ if inst.has_lock_prefix() => self.atomic(&inst)?,
+                Mnemonic::Xchg if inst.op0_kind() == OpKind::Memory
+                    || inst.op1_kind() == OpKind::Memory => self.atomic(&inst)?,
+                Mnemonic::Xchg => self.xchg(&inst),
+                Mnemonic::Xadd | Mnemonic::Cmpxchg if inst.op0_kind() == OpKind::Memory => self.atomic(&inst)?,
+                Mnemonic::Movsb | Mnemonic::Movsw | Mnemonic::Movsd | Mnemonic::Movsq
+                | Mnemonic::Stosb | Mnemonic::Stosw | Mnemonic::Stosd | Mnemonic::Stosq
+                | Mnemonic::Lodsb | Mnemonic::Lodsw | Mnemonic::Lodsd | Mnemonic::Lodsq
+                | Mnemonic::Cmpsb | Mnemonic::Cmpsw | Mnemonic::Cmpsd | Mnemonic::Cmpsq
+                | Mnemonic::Scasb | Mnemonic::Scasw | Mnemonic::Scasd | Mnemonic::Scasq
+                    if Self::is_string_lifted(&inst) => self.string(&inst),
                 Mnemonic::Mov => self.mov(&inst),
+                Mnemonic::Movaps | Mnemonic::Movups | Mnemonic::Movapd | Mnemonic::Movupd
+                | Mnemonic::Movdqa | Mnemonic::Movdqu => self.movdqu(&inst),
+                Mnemonic::Paddb | Mnemonic::Paddw | Mnemonic::Paddd | Mnemonic::Paddq
+                | Mnemonic::Psubb | Mnemonic::Psubw | Mnemonic::Psubd | Mnemonic::Psubq
+                | Mnemonic::Pand | Mnemonic::Pandn | Mnemonic::Por | Mnemonic::Pxor
+                | Mnemonic::Pcmpeqb | Mnemonic::Pcmpeqw | Mnemonic::Pcmpeqd | Mnemonic::Pcmpeqq
+                    if inst.op0_register().is_xmm() => self.packed(&inst),
+                Mnemonic::Pshufd => self.pshufd(&inst),
+                // movd and movq on mmx registers and movsd string forms that aren't lifted still go through vmexec
+                Mnemonic::Movd | Mnemonic::Movq | Mnemonic::Movss | Mnemonic::Movsd
+                    if !inst.op0_register().is_mm() && !inst.op1_register().is_mm()
+                        && !inst.op_kinds().any(|kind| matches!(kind, OpKind::MemorySegRSI | OpKind::MemorySegESI
+                            | OpKind::MemoryESRDI | OpKind::MemoryESEDI)) => self.mov_scalar(&inst),
+                Mnemonic::Addss | Mnemonic::Addsd | Mnemonic::Subss | Mnemonic::Subsd
+                | Mnemonic::Mulss | Mnemonic::Mulsd | Mnemonic::Divss | Mnemonic::Divsd
+                | Mnemonic::Minss | Mnemonic::Minsd | Mnemonic::Maxss | Mnemonic::Maxsd
+                | Mnemonic::Sqrtss | Mnemonic::Sqrtsd => self.float_arith(&inst),
+                Mnemonic::Ucomiss | Mnemonic::Ucomisd | Mnemonic::Comiss | Mnemonic::Comisd => self.float_compare(&inst),
+                Mnemonic::Cvtsi2ss | Mnemonic::Cvtsi2sd => self.cvtsi2f(&inst),
+                Mnemonic::Cvtss2si | Mnemonic::Cvtsd2si
+                | Mnemonic::Cvttss2si | Mnemonic::Cvttsd2si => self.cvtf2si(&inst),
+                Mnemonic::Cvtss2sd | Mnemonic::Cvtsd2ss => self.cvtf2f(&inst),
                 Mnemonic::Movzx => self.movzx(&inst),
+                Mnemonic::Movsx | Mnemonic::Movsxd => self.movsx(&inst),
+                Mnemonic::Cbw | Mnemonic::Cwde | Mnemonic::Cdqe => self.cbw(&inst),
+                Mnemonic::Cwd | Mnemonic::Cdq | Mnemonic::Cqo => self.cwd(&inst),
                 Mnemonic::Add => self.add(&inst),
+                Mnemonic::Adc => self.adc(&inst),
                 Mnemonic::Sub => self.sub(&inst),
+                Mnemonic::Sbb => self.sbb(&inst),
+                Mnemonic::Neg => self.neg(&inst),
                 Mnemonic::Inc => self.inc(&inst),
+                Mnemonic::Pushfq => self.pushfq(),
+                Mnemonic::Popfq => self.popfq(),
+                Mnemonic::Lahf => self.lahf(),
+                Mnemonic::Sahf => self.sahf(),
                 Mnemonic::Dec => self.dec(&inst),
                 Mnemonic::Div => self.div(&inst, false),
                 Mnemonic::Idiv => self.div(&inst, true),
+                Mnemonic::Shl | Mnemonic::Sal => self.shl(&inst),
                 Mnemonic::Shr => self.shr(&inst),
-                Mnemonic::Mul => self.mul(&inst),
+                Mnemonic::Sar => self.sar(&inst),
+                Mnemonic::Rol => self.rol(&inst),
+                Mnemonic::Ror => self.ror(&inst),
+                Mnemonic::Rcl => self.rcl(&inst),
+                Mnemonic::Rcr => self.rcr(&inst),
+                Mnemonic::Shld | Mnemonic::Shrd => self.double_shift(&inst),
+                Mnemonic::Mul => self.mul(&inst, false),
                 Mnemonic::Imul => self.imul(&inst),
                 Mnemonic::And => self.and(&inst),
                 Mnemonic::Or => self.or(&inst),
                 Mnemonic::Xor => self.xor(&inst),
                 Mnemonic::Not => self.not(&inst),
                 Mnemonic::Cmp => self.cmp(&inst),
+                Mnemonic::Test => self.test(&inst),
+                Mnemonic::Bt | Mnemonic::Bts | Mnemonic::Btr | Mnemonic::Btc => self.bit_test(&inst),
+                Mnemonic::Bsf | Mnemonic::Bsr => self.bit_scan(&inst),
+                Mnemonic::Tzcnt | Mnemonic::Lzcnt | Mnemonic::Popcnt => self.bit_count(&inst),
+                Mnemonic::Bswap => self.bswap(&inst),
+                Mnemonic::Seto | Mnemonic::Setno | Mnemonic::Setb | Mnemonic::Setae
+                | Mnemonic::Sete | Mnemonic::Setne | Mnemonic::Setbe | Mnemonic::Seta
+                | Mnemonic::Sets | Mnemonic::Setns | Mnemonic::Setp | Mnemonic::Setnp
+                | Mnemonic::Setl | Mnemonic::Setge | Mnemonic::Setle | Mnemonic::Setg => self.setcc(&inst),
+                Mnemonic::Cmovo | Mnemonic::Cmovno | Mnemonic::Cmovb | Mnemonic::Cmovae
+                | Mnemonic::Cmove | Mnemonic::Cmovne | Mnemonic::Cmovbe | Mnemonic::Cmova
+                | Mnemonic::Cmovs | Mnemonic::Cmovns | Mnemonic::Cmovp | Mnemonic::Cmovnp
+                | Mnemonic::Cmovl | Mnemonic::Cmovge | Mnemonic::Cmovle | Mnemonic::Cmovg => self.cmovcc(&inst),
                 Mnemonic::Lea => self.lea(&inst),
-                Mnemonic::Ret => self.ret(),
+                Mnemonic::Ret => self.ret(&inst, self.routines.contains(&entry)),
+                Mnemonic::Leave => self.leave(),
+                Mnemonic::Enter => self.enter(&inst),
                 Mnemonic::Push => self.push(&inst),
                 Mnemonic::Pop => self.pop(&inst),
+                Mnemonic::Jmp if inst.is_jmp_near_indirect() => match cfg.jump_tables.get(&inst.ip()) {
+                    Some(table) => unresolved_jmps += self.jmp_table(&inst, table, &cfg, &mut jmp_map),
+                    None => self.jmp_exit(&inst),
+                },
                 // call is executed unvirtualized
                 Mnemonic::Jmp | Mnemonic::Je | Mnemonic::Jne | Mnemonic::Jbe
-                | Mnemonic::Ja | Mnemonic::Jle | Mnemonic::Jg | Mnemonic::Jae => {
-                    if !inst.is_jcc_short_or_near() && !inst.is_jmp_short_or_near() {
+                | Mnemonic::Ja | Mnemonic::Jle | Mnemonic::Jg | Mnemonic::Jae
+                | Mnemonic::Jb | Mnemonic::Jl | Mnemonic::Jge | Mnemonic::Js
+                | Mnemonic::Jns | Mnemonic::Jo | Mnemonic::Jno | Mnemonic::Jp
+                | Mnemonic::Jnp | Mnemonic::Jrcxz | Mnemonic::Jecxz => {
+                    if !inst.is_jcc_short_or_near() && !inst.is_jmp_short_or_near()
+                        && !inst.is_jcx_short() {
                         let mut output = String::new();
                         NasmFormatter::new().format(&inst, &mut output);
                         anyhow::bail!("unsupported jmp: {}", output);
@@ -184,14 +411,22 @@ impl Virtualizer {
 
                     let target = inst.near_branch_target();
 
-                    if target > inst.ip() {
+                    // tail jumps continue natively in the other function
+                    if !cfg.contains(target) {
+                        match condition {
+                            JmpCond::Jmp => self.jmp_exit_to(target),
+                            _ => {
+                                tail_jmps.push((self.asm.len() as u64, target));
+                                self.asm.jmp(condition, 0);
+                            }
+                        }
+                    // targets that weren't virtualized yet are patched once all chunks are done
+                    } else if let Some(offset) = target_map.get(&target) {
+                        self.asm.jmp(condition, self.asm.len().wrapping_sub(*offset as usize) as u64);
+                    } else {
                         jmp_map.insert(self.asm.len() as u64, target);
                         self.asm.jmp(condition, 0);
                         unresolved_jmps += 1;
-                    } else if target_map.contains_key(&target) {
-                        self.asm.jmp(condition, self.asm.len().wrapping_sub(*target_map.get(&target).unwrap() as usize) as u64);
-                    } else {
-                        unresolved_jmps += 1;
                     }
                 }
                 _ => {
@@ -204,7 +439,10 @@ impl Virtualizer {
                         anyhow::bail!("unsupported jmp instruction");
                     }
 
-                    if inst.is_call_near() {
+                    if inst.is_call_near() && self.routines.contains(&inst.near_branch_target()) {
+                        self.calls.push((self.asm.len() + 2, inst.near_branch_target()));
+                        self.asm.vmcall(0);
+                    } else if inst.is_call_near() {
                         self.asm.call(inst, self.image_base)?;
                     } else {
                         self.asm.vmexec(inst, self.pe.as_ref(), self.image_base)?;
@@ -213,9 +451,16 @@ impl Virtualizer {
             }
         }
 
+        for (jmp_offset, target) in tail_jmps {
+            self.asm.patch(jmp_offset as usize + 3, jmp_offset.wrapping_sub(self.asm.len() as u64));
+            self.jmp_exit_to(target);
+        }
+
         for (jmp_offset, ip) in jmp_map.into_iter() {
-            self.asm.patch(jmp_offset as usize + 3, jmp_offset.wrapping_sub(*target_map.get(&ip).unwrap()));
-            unresolved_jmps -= 1;
+            if let Some(target) = target_map.get(&ip) {
+                self.asm.patch(jmp_offset as usize + 3, jmp_offset.wrapping_sub(*target));
+                unresolved_jmps -= 1;
+            }
         }
 
         anyhow::ensure!(unresolved_jmps == 0, "{unresolved_jmps} unresolved jmps");
@@ -230,6 +475,224 @@ impl Virtualizer {
         );
     }
 
+    // full 128 bit moves, alignment of movaps and movdqa isn't checked
+    fn movdqu(&mut self, inst: &Instruction) {
+        self.mov(inst);
+    }
+
+    // movd, movq, movss and movsd move the low 32 or 64 bits. Writing a xmm register zeroes the upper
+    // lanes unless movss or movsd copy between two xmm registers, gpr writes zero extend as usual
+    fn mov_scalar(&mut self, inst: &Instruction) {
+        let size = match inst.mnemonic() {
+            Mnemonic::Movd | Mnemonic::Movss => OpSize::Dword,
+            _ => OpSize::Qword,
+        };
+
+        self.load_scalar(inst, 1, size);
+
+        let dst = inst.op0_register();
+        if inst.op0_kind() == OpKind::Register && dst.is_gpr() {
+            vmasm!(self, store_reg, dst;);
+            return;
+        }
+        self.store_scalar(inst, 0, size);
+
+        let merge = inst.op1_register().is_xmm() && matches!(inst.mnemonic(), Mnemonic::Movss | Mnemonic::Movsd);
+        if dst.is_xmm() && !merge {
+            if let OpSize::Dword = size {
+                vmasm!(self, const_::<u32>, 0;);
+                self.xmm_lane(dst, 4);
+                vmasm!(self, store::<u32>;);
+            }
+            vmasm!(self, const_::<u64>, 0;);
+            self.xmm_lane(dst, 8);
+            vmasm!(self, store::<u64>;);
+        }
+    }
+
+    // packed integer ops on xmm registers, mmx forms still go through vmexec
+    fn packed(&mut self, inst: &Instruction) {
+        let (op, lane) = match inst.mnemonic() {
+            Mnemonic::Paddb => (PackedOp::Add, OpSize::Byte),
+            Mnemonic::Paddw => (PackedOp::Add, OpSize::Word),
+            Mnemonic::Paddd => (PackedOp::Add, OpSize::Dword),
+            Mnemonic::Paddq => (PackedOp::Add, OpSize::Qword),
+            Mnemonic::Psubb => (PackedOp::Sub, OpSize::Byte),
+            Mnemonic::Psubw => (PackedOp::Sub, OpSize::Word),
+            Mnemonic::Psubd => (PackedOp::Sub, OpSize::Dword),
+            Mnemonic::Psubq => (PackedOp::Sub, OpSize::Qword),
+            Mnemonic::Pcmpeqb => (PackedOp::CmpEq, OpSize::Byte),
+            Mnemonic::Pcmpeqw => (PackedOp::CmpEq, OpSize::Word),
+            Mnemonic::Pcmpeqd => (PackedOp::CmpEq, OpSize::Dword),
+            Mnemonic::Pcmpeqq => (PackedOp::CmpEq, OpSize::Qword),
+            Mnemonic::Pand => (PackedOp::And, OpSize::Qword),
+            Mnemonic::Pandn => (PackedOp::AndNot, OpSize::Qword),
+            Mnemonic::Por => (PackedOp::Or, OpSize::Qword),
+            _ => (PackedOp::Xor, OpSize::Qword),
+        };
+
+        vmasm!(self,
+            load_operand, inst, 0;
+            load_operand, inst, 1;
+        );
+        match lane {
+            OpSize::Byte => vmasm!(self, packed::<u8>, op;),
+            OpSize::Word => vmasm!(self, packed::<u16>, op;),
+            OpSize::Dword => vmasm!(self, packed::<u32>, op;),
+            OpSize::Qword => vmasm!(self, packed::<u64>, op;),
+        }
+        vmasm!(self, store_operand, inst, 0;);
+    }
+
+    fn pshufd(&mut self, inst: &Instruction) {
+        vmasm!(self,
+            load_operand, inst, 1;
+            load_operand, inst, 2;
+            pshufd;
+            store_operand, inst, 0;
+        );
+    }
+
+    // scalar single and double precision arithmetic only writes the low lane of the destination
+    fn float_arith(&mut self, inst: &Instruction) {
+        let (op, size) = match inst.mnemonic() {
+            Mnemonic::Addss => (FloatOp::Add, OpSize::Dword),
+            Mnemonic::Addsd => (FloatOp::Add, OpSize::Qword),
+            Mnemonic::Subss => (FloatOp::Sub, OpSize::Dword),
+            Mnemonic::Subsd => (FloatOp::Sub, OpSize::Qword),
+            Mnemonic::Mulss => (FloatOp::Mul, OpSize::Dword),
+            Mnemonic::Mulsd => (FloatOp::Mul, OpSize::Qword),
+            Mnemonic::Divss => (FloatOp::Div, OpSize::Dword),
+            Mnemonic::Divsd => (FloatOp::Div, OpSize::Qword),
+            Mnemonic::Minss => (FloatOp::Min, OpSize::Dword),
+            Mnemonic::Minsd => (FloatOp::Min, OpSize::Qword),
+            Mnemonic::Maxss => (FloatOp::Max, OpSize::Dword),
+            Mnemonic::Maxsd => (FloatOp::Max, OpSize::Qword),
+            Mnemonic::Sqrtss => (FloatOp::Sqrt, OpSize::Dword),
+            _ => (FloatOp::Sqrt, OpSize::Qword),
+        };
+
+        // sqrt only reads the source
+        if !matches!(op, FloatOp::Sqrt) {
+            self.load_scalar(inst, 0, size);
+        }
+        self.load_scalar(inst, 1, size);
+        self.float_sized(op, size);
+        self.store_scalar(inst, 0, size);
+    }
+
+    // ucomis and comis set zf, pf and cf and clear of, sf and af
+    fn float_compare(&mut self, inst: &Instruction) {
+        let (op, size) = match inst.mnemonic() {
+            Mnemonic::Ucomiss => (FloatOp::UComi, OpSize::Dword),
+            Mnemonic::Ucomisd => (FloatOp::UComi, OpSize::Qword),
+            Mnemonic::Comiss => (FloatOp::Comi, OpSize::Dword),
+            _ => (FloatOp::Comi, OpSize::Qword),
+        };
+
+        self.load_scalar(inst, 0, size);
+        self.load_scalar(inst, 1, size);
+        self.float_sized(op, size);
+    }
+
+    fn cvtsi2f(&mut self, inst: &Instruction) {
+        let size = match inst.mnemonic() {
+            Mnemonic::Cvtsi2ss => OpSize::Dword,
+            _ => OpSize::Qword,
+        };
+        let int_size = match inst.op1_kind() {
+            OpKind::Memory => inst.memory_size().size(),
+            _ => inst.op1_register().size(),
+        };
+
+        match int_size {
+            4 => {
+                self.load_scalar(inst, 1, OpSize::Dword);
+                self.float_sized(FloatOp::FromInt32, size);
+            }
+            _ => {
+                self.load_scalar(inst, 1, OpSize::Qword);
+                self.float_sized(FloatOp::FromInt64, size);
+            }
+        }
+        self.store_scalar(inst, 0, size);
+    }
+
+    // the rounding mode of cvts*2si comes from mxcsr, cvtts*2si always truncates
+    fn cvtf2si(&mut self, inst: &Instruction) {
+        let (truncate, size) = match inst.mnemonic() {
+            Mnemonic::Cvtss2si => (false, OpSize::Dword),
+            Mnemonic::Cvtsd2si => (false, OpSize::Qword),
+            Mnemonic::Cvttss2si => (true, OpSize::Dword),
+            _ => (true, OpSize::Qword),
+        };
+        let op = match (truncate, inst.op0_register().size()) {
+            (false, 4) => FloatOp::ToInt32,
+            (false, _) => FloatOp::ToInt64,
+            (true, 4) => FloatOp::TruncToInt32,
+            (true, _) => FloatOp::TruncToInt64,
+        };
+
+        self.load_scalar(inst, 1, size);
+        self.float_sized(op, size);
+        vmasm!(self, store_reg, inst.op0_register(););
+    }
+
+    fn cvtf2f(&mut self, inst: &Instruction) {
+        let (src, dst) = match inst.mnemonic() {
+            Mnemonic::Cvtss2sd => (OpSize::Dword, OpSize::Qword),
+            _ => (OpSize::Qword, OpSize::Dword),
+        };
+
+        self.load_scalar(inst, 1, src);
+        self.float_sized(FloatOp::ToOther, src);
+        self.store_scalar(inst, 0, dst);
+    }
+
+    fn float_sized(&mut self, op: FloatOp, size: OpSize) {
+        match size {
+            OpSize::Dword => vmasm!(self, float::<u32>, op;),
+            _ => vmasm!(self, float::<u64>, op;),
+        }
+    }
+
+    /// Pushes the address of the low scalar of an operand, either memory, the low lane of a xmm
+    /// register or a general purpose register
+    fn scalar_address(&mut self, inst: &Instruction, operand: u32) {
+        let reg = inst.op_register(operand);
+        match inst.op_kind(operand) {
+            OpKind::Memory => vmasm!(self, lea_operand, inst;),
+            _ if reg.is_xmm() => self.xmm_lane(reg, 0),
+            _ => vmasm!(self, vmctx; const_::<u64>, reg.reg_offset(); vmadd;),
+        }
+    }
+
+    fn load_scalar(&mut self, inst: &Instruction, operand: u32, size: OpSize) {
+        self.scalar_address(inst, operand);
+        match size {
+            OpSize::Dword => vmasm!(self, load::<u32>;),
+            _ => vmasm!(self, load::<u64>;),
+        }
+    }
+
+    /// Stores the scalar on top of the stack, the upper lanes of a xmm register are kept
+    fn store_scalar(&mut self, inst: &Instruction, operand: u32, size: OpSize) {
+        self.scalar_address(inst, operand);
+        match size {
+            OpSize::Dword => vmasm!(self, store::<u32>;),
+            _ => vmasm!(self, store::<u64>;),
+        }
+    }
+
+    /// Pushes the address of the byte at `offset` in a xmm register
+    fn xmm_lane(&mut self, reg: iced_x86::Register, offset: u64) {
+        vmasm!(self,
+            vmctx;
+            const_::<u64>, reg.reg_offset() + offset;
+            vmadd;
+        );
+    }
+
     fn movzx(&mut self, inst: &Instruction) {
         vmasm!(self,
             load_operand, inst, 1;
@@ -237,36 +700,106 @@ impl Virtualizer {
         );
     }
 
+    fn movsx(&mut self, inst: &Instruction) {
+        let src_size = if inst.op1_kind() == OpKind::Memory {
+            inst.memory_size().size()
+        } else {
+            inst.op1_register().size()
+        };
+
+        vmasm!(self, load_operand, inst, 1;);
+        self.sign_extend_to(src_size, inst.op0_register().size());
+        vmasm!(self, store_operand, inst, 0;);
+    }
+
+    // cbw, cwde, cdqe
+    fn cbw(&mut self, inst: &Instruction) {
+        use iced_x86::Register::*;
+
+        let (src, dst) = match inst.mnemonic() {
+            Mnemonic::Cbw => (AL, AX),
+            Mnemonic::Cwde => (AX, EAX),
+            _ => (EAX, RAX),
+        };
+
+        vmasm!(self, load_reg, src;);
+        self.sign_extend_to(src.size(), dst.size());
+        vmasm!(self, store_reg, dst;);
+    }
+
+    // cwd, cdq, cqo, the sign fill ends up in the upper half of the extended value
+    fn cwd(&mut self, inst: &Instruction) {
+        use iced_x86::Register::*;
+
+        let (src, dst) = match inst.mnemonic() {
+            Mnemonic::Cwd => (AX, DX),
+            Mnemonic::Cdq => (EAX, EDX),
+            _ => (RAX, RDX),
+        };
+
+        vmasm!(self, load_reg, src;);
+        self.sign_extend_to(src.size(), src.size() * 2);
+        // write back the lower half with store so the upper bits of rax stay untouched
+        vmasm!(self,
+            vmctx;
+            const_::<u64>, src.reg_offset();
+            vmadd;
+        );
+        match OpSize::from(src) {
+            OpSize::Word => vmasm!(self, store::<u16>;),
+            OpSize::Dword => vmasm!(self, store::<u32>;),
+            _ => vmasm!(self, store::<u64>;),
+        }
+        vmasm!(self, store_reg, dst;);
+    }
+
+    /// Sign extends the value on the stack by doubling its size until it reaches `dst_size`
+    fn sign_extend_to(&mut self, mut src_size: usize, dst_size: usize) {
+        while src_size < dst_size {
+            match OpSize::try_from(src_size as u8).unwrap() {
+                OpSize::Byte => vmasm!(self, sign_extend::<u8>;),
+                OpSize::Word => vmasm!(self, sign_extend::<u16>;),
+                OpSize::Dword => vmasm!(self, sign_extend::<u32>;),
+                OpSize::Qword => vmasm!(self, sign_extend::<u64>;),
+            }
+            src_size *= 2;
+        }
+    }
+
     // https://blog.back.engineering/17/05/2021/#ADD
     fn add(&mut self, inst: &Instruction) {
         binary_op!(self, inst, add)
     }
 
+    fn adc(&mut self, inst: &Instruction) {
+        binary_op!(self, inst, adc)
+    }
+
     fn sub(&mut self, inst: &Instruction) {
         binary_op!(self, inst, sub)
     }
 
+    fn sbb(&mut self, inst: &Instruction) {
+        binary_op!(self, inst, sbb)
+    }
+
+    fn neg(&mut self, inst: &Instruction) {
+        vmasm!(self, load_operand, inst, 0;);
+        vmasm_sized!(self, neg, inst;);
+        vmasm!(self, store_operand, inst, 0;);
+    }
+
+    // inc and dec keep cf
     fn inc(&mut self, inst: &Instruction) {
-        vmasm!(self,
-            load_operand, inst, 0;
-        );
-        vmasm_sized!(self,
-            const_, inst, 1;
-            add, inst;
-        );
-        vmasm!(self,store_operand, inst, 0;);
+        vmasm!(self, load_operand, inst, 0;);
+        vmasm_sized!(self, inc, inst;);
+        vmasm!(self, store_operand, inst, 0;);
     }
 
     fn dec(&mut self, inst: &Instruction) {
-        vmasm!(self,
-            load_operand, inst, 0;
-            const_::<u64>, 1;
-        );
-        vmasm_sized!(self,
-            const_, inst, 1;
-            sub, inst;
-        );
-        vmasm!(self,store_operand, inst, 0;);
+        vmasm!(self, load_operand, inst, 0;);
+        vmasm_sized!(self, dec, inst;);
+        vmasm!(self, store_operand, inst, 0;);
     }
 
     fn div(&mut self, inst: &Instruction, signed: bool) {
@@ -310,31 +843,64 @@ impl Virtualizer {
         };
     }
 
+    // count is either imm8 or cl, both end up as byte on the stack
+    fn shl(&mut self, inst: &Instruction) {
+        binary_op!(self, inst, shl)
+    }
+
     fn shr(&mut self, inst: &Instruction) {
-        for _ in 0..inst.immediate8() {
-            vmasm!(self, load_operand, inst, 0;);
-            vmasm_sized!(self,
-                const_, inst, 2;
-                shr, inst;
-            );
-            vmasm!(self, store_operand, inst, 0;);
+        binary_op!(self, inst, shr)
+    }
+
+    fn sar(&mut self, inst: &Instruction) {
+        binary_op!(self, inst, sar)
+    }
+
+    fn rol(&mut self, inst: &Instruction) {
+        binary_op!(self, inst, rot_left)
+    }
+
+    fn ror(&mut self, inst: &Instruction) {
+        binary_op!(self, inst, rot_right)
+    }
+
+    fn rcl(&mut self, inst: &Instruction) {
+        binary_op!(self, inst, rcl)
+    }
+
+    fn rcr(&mut self, inst: &Instruction) {
+        binary_op!(self, inst, rcr)
+    }
+
+    // shld, shrd, the count is either imm8 or cl
+    fn double_shift(&mut self, inst: &Instruction) {
+        vmasm!(self,
+            load_operand, inst, 0;
+            load_operand, inst, 1;
+            load_operand, inst, 2;
+        );
+        match inst.mnemonic() {
+            Mnemonic::Shld => vmasm_sized!(self, shld, inst;),
+            _ => vmasm_sized!(self, shrd, inst;),
         }
+        vmasm!(self, store_operand, inst, 0;);
     }
 
-    fn mul(&mut self, inst: &Instruction) {
+    // one operand forms store the double width product in rdx:rax, edx:eax, dx:ax or ax
+    fn mul(&mut self, inst: &Instruction, signed: bool) {
         use iced_x86::Register::*;
 
-        match OpSize::try_from(inst.op0_register()).unwrap() {
+        match OpSize::try_from(inst).unwrap() {
             OpSize::Byte => vmasm!(self,
                 load_reg, AL;
                 load_operand, inst, 0;
-                mul::<u8>;
+                mul::<u8>, signed;
                 store_reg, AX;
             ),
             OpSize::Word => vmasm!(self,
                 load_reg, AX;
                 load_operand, inst, 0;
-                mul::<u16>;
+                mul::<u16>, signed;
                 split::<u16>;
                 store_reg, AX;
                 store_reg, DX;
@@ -342,7 +908,7 @@ impl Virtualizer {
             OpSize::Dword => vmasm!(self,
                 load_reg, EAX;
                 load_operand, inst, 0;
-                mul::<u32>;
+                mul::<u32>, signed;
                 split::<u32>;
                 store_reg, EAX;
                 store_reg, EDX;
@@ -350,8 +916,8 @@ impl Virtualizer {
             OpSize::Qword => vmasm!(self,
                 load_reg, RAX;
                 load_operand, inst, 0;
-                mul::<u64>;
-                split::<u32>;
+                mul::<u64>, signed;
+                split::<u64>;
                 store_reg, RAX;
                 store_reg, RDX;
             )
@@ -360,14 +926,14 @@ impl Virtualizer {
 
     fn imul(&mut self, inst: &Instruction) {
         match inst.op_count() {
-            1 => self.mul(inst),
-            2 => binary_op!(self, inst, mul),
+            1 => self.mul(inst, true),
+            2 => binary_op!(self, inst, imul_trunc),
             3 => {
                 vmasm!(self,
                     load_operand, inst, 1;
                     load_operand, inst, 2;
                 );
-                vmasm_sized!(self, mul, inst;);
+                vmasm_sized!(self, imul_trunc, inst;);
                 vmasm!(self, store_operand, inst, 0;);
             }
             _ => unreachable!()
@@ -400,6 +966,240 @@ impl Virtualizer {
         vmasm_sized!(self, cmp, inst;);
     }
 
+    fn test(&mut self, inst: &Instruction) {
+        vmasm!(self,
+            load_operand, inst, 0;
+            load_operand, inst, 1;
+        );
+        vmasm_sized!(self, test, inst;);
+    }
+
+    fn setcc(&mut self, inst: &Instruction) {
+        vmasm!(self,
+            cond::<u8>, inst.condition_code().into();
+            store_operand, inst, 0;
+        );
+    }
+
+    // dst = dst ^ ((src ^ dst) & -cond), rflags are restored after the select
+    fn cmovcc(&mut self, inst: &Instruction) {
+        vmasm!(self, load_rflags;);
+        vmasm_sized!(self,
+            const_, inst, 0;
+            cond, inst, inst.condition_code().into();
+            sub, inst;
+        );
+        vmasm!(self,
+            load_operand, inst, 1;
+            load_operand, inst, 0;
+        );
+        vmasm_sized!(self,
+            xor, inst;
+            and, inst;
+        );
+        vmasm!(self, load_operand, inst, 0;);
+        vmasm_sized!(self, xor, inst;);
+        vmasm!(self,
+            store_operand, inst, 0;
+            store_rflags;
+        );
+    }
+
+    fn xchg(&mut self, inst: &Instruction) {
+        vmasm!(self,
+            load_operand, inst, 0;
+            load_operand, inst, 1;
+            store_operand, inst, 0;
+            store_operand, inst, 1;
+        );
+    }
+
+    // the vm does the locked operation itself on the target address, xchg, xadd and cmpxchg
+    // with a memory operand are always lifted like this even without a lock prefix
+    fn atomic(&mut self, inst: &Instruction) -> anyhow::Result<()> {
+        use iced_x86::Register::*;
+
+        let op = match inst.mnemonic() {
+            Mnemonic::Xchg => AtomicOp::Xchg,
+            Mnemonic::Xadd => AtomicOp::Xadd,
+            Mnemonic::Cmpxchg => AtomicOp::CmpXchg,
+            Mnemonic::Add => AtomicOp::Add,
+            Mnemonic::Sub => AtomicOp::Sub,
+            Mnemonic::And => AtomicOp::And,
+            Mnemonic::Or => AtomicOp::Or,
+            Mnemonic::Xor => AtomicOp::Xor,
+            Mnemonic::Inc => AtomicOp::Inc,
+            Mnemonic::Dec => AtomicOp::Dec,
+            Mnemonic::Not => AtomicOp::Not,
+            Mnemonic::Bts => AtomicOp::Bts,
+            Mnemonic::Btr => AtomicOp::Btr,
+            Mnemonic::Btc => AtomicOp::Btc,
+            // lock adc, sbb, neg and cmpxchg8b/16b have no handler, vmexec runs just the locked instruction
+            // natively so it stays a single atomic operation
+            _ => return self.asm.vmexec(*inst, self.pe.as_ref(), self.image_base),
+        };
+
+        // xchg can have the memory operand on either side
+        let (mem, src) = if inst.op0_kind() == OpKind::Memory { (0, 1) } else { (1, 0) };
+        anyhow::ensure!(inst.op_kind(mem) == OpKind::Memory, "locked instruction without memory operand");
+
+        vmasm!(self, lea_operand, inst;);
+
+        if let AtomicOp::CmpXchg = op {
+            match OpSize::try_from(inst).unwrap() {
+                OpSize::Byte => vmasm!(self, load_reg, AL;),
+                OpSize::Word => vmasm!(self, load_reg, AX;),
+                OpSize::Dword => vmasm!(self, load_reg, EAX;),
+                OpSize::Qword => vmasm!(self, load_reg, RAX;),
+            }
+        }
+
+        if matches!(op, AtomicOp::Bts | AtomicOp::Btr | AtomicOp::Btc) {
+            if inst.op1_kind() == OpKind::Register {
+                vmasm!(self, load_operand, inst, 1;);
+                vmasm_sized!(self, bit_addr, inst;);
+            }
+            self.bit_offset(inst);
+        } else if !matches!(op, AtomicOp::Inc | AtomicOp::Dec | AtomicOp::Not) {
+            vmasm!(self, load_operand, inst, src;);
+        }
+
+        vmasm_sized!(self, atomic, inst, op;);
+
+        if let AtomicOp::Xchg | AtomicOp::Xadd = op {
+            vmasm!(self, store_operand, inst, src;);
+        }
+
+        Ok(())
+    }
+
+    // bt, bts, btr, btc
+    fn bit_test(&mut self, inst: &Instruction) {
+        let write = inst.mnemonic() != Mnemonic::Bt;
+
+        // with a register offset the memory operand is a bit string, the offset can reach beyond
+        // the operand in both directions and selects the element holding the bit
+        if inst.op0_kind() == OpKind::Memory && inst.op1_kind() == OpKind::Register {
+            vmasm!(self,
+                lea_operand, inst;
+                load_operand, inst, 1;
+            );
+            vmasm_sized!(self, bit_addr, inst; load, inst;);
+        } else {
+            vmasm!(self, load_operand, inst, 0;);
+        }
+
+        self.bit_offset(inst);
+
+        match inst.mnemonic() {
+            Mnemonic::Bt => vmasm_sized!(self, bt, inst;),
+            Mnemonic::Bts => vmasm_sized!(self, bts, inst;),
+            Mnemonic::Btr => vmasm_sized!(self, btr, inst;),
+            _ => vmasm_sized!(self, btc, inst;),
+        }
+
+        if write && inst.op0_kind() == OpKind::Memory && inst.op1_kind() == OpKind::Register {
+            vmasm!(self,
+                lea_operand, inst;
+                load_operand, inst, 1;
+            );
+            vmasm_sized!(self, bit_addr, inst; store, inst;);
+        } else if write {
+            vmasm!(self, store_operand, inst, 0;);
+        }
+    }
+
+    /// Pushes the bit offset of the bt family, immediates are masked to the operand size
+    fn bit_offset(&mut self, inst: &Instruction) {
+        if inst.op1_kind() == OpKind::Register {
+            vmasm!(self, load_operand, inst, 1;);
+        } else {
+            let offset = inst.immediate8() as u32 % (OpSize::try_from(inst).unwrap() as u32 * 8);
+            vmasm_sized!(self, const_, inst, offset as _;);
+        }
+    }
+
+    // bsf, bsr, the destination is kept if the source is zero
+    fn bit_scan(&mut self, inst: &Instruction) {
+        vmasm!(self,
+            load_operand, inst, 0;
+            load_operand, inst, 1;
+        );
+        match inst.mnemonic() {
+            Mnemonic::Bsf => vmasm_sized!(self, bsf, inst;),
+            _ => vmasm_sized!(self, bsr, inst;),
+        }
+        vmasm!(self, store_operand, inst, 0;);
+    }
+
+    // tzcnt, lzcnt, popcnt
+    fn bit_count(&mut self, inst: &Instruction) {
+        vmasm!(self, load_operand, inst, 1;);
+        match inst.mnemonic() {
+            Mnemonic::Tzcnt => vmasm_sized!(self, tzcnt, inst;),
+            Mnemonic::Lzcnt => vmasm_sized!(self, lzcnt, inst;),
+            _ => vmasm_sized!(self, popcnt, inst;),
+        }
+        vmasm!(self, store_operand, inst, 0;);
+    }
+
+    fn bswap(&mut self, inst: &Instruction) {
+        vmasm!(self, load_operand, inst, 0;);
+        vmasm_sized!(self, bswap, inst;);
+        vmasm!(self, store_operand, inst, 0;);
+    }
+
+    // string instructions with 32 bit addressing or a segment override still go through vmexec,
+    // movsd and cmpsd also name the sse instructions which have no string operands
+    fn is_string_lifted(inst: &Instruction) -> bool {
+        inst.segment_prefix() == iced_x86::Register::None
+            && inst.op_kinds().any(|kind| matches!(kind, OpKind::MemorySegRSI | OpKind::MemoryESRDI))
+    }
+
+    // rep loops until rcx is zero, repe and repne also stop after a cmps or scas that cleared or set zf
+    fn string(&mut self, inst: &Instruction) {
+        use iced_x86::Register::RCX;
+
+        let op = match inst.mnemonic() {
+            Mnemonic::Movsb | Mnemonic::Movsw | Mnemonic::Movsd | Mnemonic::Movsq => StringOp::Movs,
+            Mnemonic::Stosb | Mnemonic::Stosw | Mnemonic::Stosd | Mnemonic::Stosq => StringOp::Stos,
+            Mnemonic::Lodsb | Mnemonic::Lodsw | Mnemonic::Lodsd | Mnemonic::Lodsq => StringOp::Lods,
+            Mnemonic::Cmpsb | Mnemonic::Cmpsw | Mnemonic::Cmpsd | Mnemonic::Cmpsq => StringOp::Cmps,
+            _ => StringOp::Scas,
+        };
+
+        if !inst.has_rep_prefix() && !inst.has_repne_prefix() {
+            vmasm_sized!(self, string, inst, op;);
+            return;
+        }
+
+        let top = self.asm.len();
+        let mut exits = vec![top];
+        self.asm.jmp(JmpCond::Jrcxz, 0);
+
+        vmasm_sized!(self, string, inst, op;);
+        vmasm!(self,
+            load_reg, RCX;
+            const_::<u64>, 1;
+            vmsub;
+            store_reg, RCX;
+        );
+
+        if let StringOp::Cmps | StringOp::Scas = op {
+            exits.push(self.asm.len());
+            match inst.has_repe_prefix() {
+                true => self.asm.jmp(JmpCond::Jne, 0),
+                false => self.asm.jmp(JmpCond::Je, 0),
+            }
+        }
+
+        self.asm.jmp(JmpCond::Jmp, self.asm.len().wrapping_sub(top) as u64);
+
+        for exit in exits {
+            self.asm.patch(exit + 3, (exit as u64).wrapping_sub(self.asm.len() as u64));
+        }
+    }
+
     fn lea(&mut self, inst: &Instruction) {
         vmasm!(self,
             lea_operand, inst;
@@ -407,10 +1207,191 @@ impl Virtualizer {
         );
     }
 
-    fn ret(&mut self) {
+    /// Translates a jump table into a compare chain over the native targets that branches to their bytecode,
+    /// anything else leaves the vm. Returns the amount of emitted jmps that still need to be resolved.
+    fn jmp_table(&mut self, inst: &Instruction, table: &JumpTable, cfg: &ControlFlowGraph, jmp_map: &mut HashMap<u64, u64>) -> usize {
+        let mut targets = table.targets.clone();
+        targets.retain(|target| cfg.contains(*target));
+        targets.sort_unstable();
+        targets.dedup();
+
+        // cmp clobbers rflags, keep them on the stack until a case is taken
+        vmasm!(self, load_rflags;);
+        let mut cases = Vec::new();
+        for target in targets {
+            vmasm!(self, load_operand, inst, 0;);
+            self.native_address(target);
+            vmasm!(self, cmp::<u64>;);
+            cases.push((self.asm.len() as u64, target));
+            self.asm.jmp(JmpCond::Je, 0);
+        }
+        vmasm!(self, store_rflags;);
+        self.jmp_exit(inst);
+
+        for (case, target) in cases.iter() {
+            self.asm.patch(*case as usize + 3, case.wrapping_sub(self.asm.len() as u64));
+            vmasm!(self, store_rflags;);
+            jmp_map.insert(self.asm.len() as u64, *target);
+            self.asm.jmp(JmpCond::Jmp, 0);
+        }
+
+        cases.len()
+    }
+
+    // leaves the vm by pushing the target as return address for vmexit
+    fn jmp_exit(&mut self, inst: &Instruction) {
+        vmasm!(self, load_operand, inst, 0;);
+        self.push_stack();
+        vmasm!(self, vmexit;);
+    }
+
+    fn jmp_exit_to(&mut self, target: u64) {
+        self.native_address(target);
+        self.push_stack();
+        vmasm!(self, vmexit;);
+    }
+
+    /// Pushes the qword on top of the vm stack onto the real stack
+    fn push_stack(&mut self) {
+        use iced_x86::Register::RSP;
+
+        self.adjust_rsp(-8);
+        vmasm!(self,
+            load_reg, RSP;
+            store::<u64>;
+        );
+    }
+
+    fn adjust_rsp(&mut self, value: i64) {
+        use iced_x86::Register::RSP;
+
+        vmasm!(self,
+            load_reg, RSP;
+            const_::<u64>, value.unsigned_abs();
+        );
+        match value.is_negative() {
+            true => vmasm!(self, vmsub;),
+            false => vmasm!(self, vmadd;),
+        }
+        vmasm!(self, store_reg, RSP;);
+    }
+
+    fn native_address(&mut self, address: u64) {
+        self.asm.const_(address);
+        if self.pe.is_some() {
+            self.asm.vmreloc(self.image_base);
+        }
+    }
+
+    /// Reads from the code being virtualized or from the pe
+    fn read(&self, chunks: &[(u64, &[u8])], address: u64, len: usize) -> Option<Vec<u8>> {
+        if let Some(data) = chunks.iter().find_map(|(ip, program)| address.checked_sub(*ip)
+            .and_then(|offset| program.get(offset as usize..offset as usize + len))) {
+            return Some(data.to_vec());
+        }
+
+        let pe = self.pe.as_ref()?;
+        let offset = pe.rva_to_offset(RVA(address.checked_sub(self.image_base)? as u32)).ok()?;
+        pe.get_slice_ref::<u8>(offset.0 as usize, len).ok().map(|data| data.to_vec())
+    }
+
+    // routines that can be called by other virtualized routines check the return address for the vm call tag
+    fn ret(&mut self, inst: &Instruction, callable: bool) {
+        use iced_x86::Register::RSP;
+
+        // ret imm16, move the return address up so returning releases the arguments too
+        if inst.op_count() == 1 && inst.immediate16() != 0 {
+            let release = inst.immediate16() as u64;
+            vmasm!(self,
+                load_reg, RSP;
+                load::<u64>;
+                load_reg, RSP;
+                const_::<u64>, release;
+                vmadd;
+                store::<u64>;
+            );
+            self.adjust_rsp(release as i64);
+        }
+
+        if !callable {
+            vmasm!(self, vmexit;);
+            return;
+        }
+
         vmasm!(self,
+            load_rflags;
+            load_reg, RSP;
+            load::<u64>;
+            const_::<u64>, VIRTUAL_RETURN;
+            test::<u64>;
+        );
+        let virtual_ret = self.asm.len();
+        self.asm.jmp(JmpCond::Jne, 0);
+        vmasm!(self,
+            store_rflags;
             vmexit;
         );
+        self.asm.patch(virtual_ret + 3, virtual_ret.wrapping_sub(self.asm.len()) as u64);
+        vmasm!(self,
+            store_rflags;
+            vmret;
+        );
+    }
+
+    // mov rsp, rbp; pop rbp
+    fn leave(&mut self) {
+        use iced_x86::Register::{RBP, RSP};
+
+        vmasm!(self,
+            load_reg, RBP;
+            store_reg, RSP;
+
+            load_reg, RSP;
+            load::<u64>;
+            store_reg, RBP;
+        );
+        self.adjust_rsp(8);
+    }
+
+    fn enter(&mut self, inst: &Instruction) {
+        use iced_x86::Register::{RBP, RSP};
+
+        let size = inst.immediate16() as u64;
+        let level = (inst.immediate8_2nd() % 32) as u64;
+
+        // push rbp, the frame starts at the pushed rbp
+        vmasm!(self, load_reg, RBP;);
+        self.push_stack();
+
+        // copy the frame pointers of the enclosing frames and push the new frame pointer after them
+        if level > 0 {
+            for _ in 1..level {
+                vmasm!(self,
+                    load_reg, RBP;
+                    const_::<u64>, 8;
+                    vmsub;
+                    store_reg, RBP;
+
+                    load_reg, RBP;
+                    load::<u64>;
+                );
+                self.push_stack();
+            }
+            vmasm!(self,
+                load_reg, RSP;
+                const_::<u64>, 8 * (level - 1);
+                vmadd;
+            );
+            self.push_stack();
+        }
+
+        vmasm!(self,
+            load_reg, RSP;
+            const_::<u64>, 8 * level;
+            vmadd;
+            store_reg, RBP;
+        );
+        self.adjust_rsp(-(size as i64));
     }
 
     fn push(&mut self, inst: &Instruction) {
@@ -430,6 +1411,54 @@ impl Virtualizer {
         );
     }
 
+    fn pushfq(&mut self) {
+        use iced_x86::Register::RSP;
+
+        vmasm!(self,
+            load_reg, RSP;
+            const_::<u64>, 8;
+            vmsub;
+            store_reg, RSP;
+
+            load_rflags;
+            load_reg, RSP;
+            store::<u64>;
+        );
+    }
+
+    fn popfq(&mut self) {
+        use iced_x86::Register::RSP;
+
+        vmasm!(self,
+            load_reg, RSP;
+            load::<u64>;
+            popf::<u64>;
+
+            load_reg, RSP;
+            const_::<u64>, 8;
+            vmadd;
+            store_reg, RSP;
+        );
+    }
+
+    // bits 1, 3 and 5 of rflags are fixed to 1, 0 and 0 so the low byte already is the lahf layout
+    fn lahf(&mut self) {
+        vmasm!(self,
+            vmctx;
+            const_::<u64>, offset_of!(Machine, rflags) as u64;
+            vmadd;
+            load::<u8>;
+            store_reg, iced_x86::Register::AH;
+        );
+    }
+
+    fn sahf(&mut self) {
+        vmasm!(self,
+            load_reg, iced_x86::Register::AH;
+            popf::<u8>;
+        );
+    }
+
     fn pop(&mut self, inst: &Instruction) {
         use iced_x86::Register::RSP;
 
@@ -463,14 +1492,42 @@ impl Asm for Virtualizer {
         self.asm.store::<T>();
     }
 
+    fn sign_extend<T: OpSized>(&mut self) {
+        self.asm.sign_extend::<T>();
+    }
+
     fn add<T: OpSized>(&mut self) {
         self.asm.add::<T>();
     }
 
+    fn adc<T: OpSized>(&mut self) {
+        self.asm.adc::<T>();
+    }
+
     fn sub<T: OpSized>(&mut self) {
         self.asm.sub::<T>();
     }
 
+    fn sbb<T: OpSized>(&mut self) {
+        self.asm.sbb::<T>();
+    }
+
+    fn neg<T: OpSized>(&mut self) {
+        self.asm.neg::<T>();
+    }
+
+    fn inc<T: OpSized>(&mut self) {
+        self.asm.inc::<T>();
+    }
+
+    fn dec<T: OpSized>(&mut self) {
+        self.asm.dec::<T>();
+    }
+
+    fn popf<T: OpSized>(&mut self) {
+        self.asm.popf::<T>();
+    }
+
     fn div<T: OpSized>(&mut self, signed: bool) {
         if signed {
             self.asm.idiv::<T>();
@@ -479,10 +1536,42 @@ impl Asm for Virtualizer {
         }
     }
 
+    fn shl<T: OpSized>(&mut self) {
+        self.asm.shl::<T>();
+    }
+
     fn shr<T: OpSized>(&mut self) {
         self.asm.shr::<T>();
     }
 
+    fn sar<T: OpSized>(&mut self) {
+        self.asm.sar::<T>();
+    }
+
+    fn rot_left<T: OpSized>(&mut self) {
+        self.asm.rot_left::<T>();
+    }
+
+    fn rot_right<T: OpSized>(&mut self) {
+        self.asm.rot_right::<T>();
+    }
+
+    fn rcl<T: OpSized>(&mut self) {
+        self.asm.rcl::<T>();
+    }
+
+    fn rcr<T: OpSized>(&mut self) {
+        self.asm.rcr::<T>();
+    }
+
+    fn shld<T: OpSized>(&mut self) {
+        self.asm.shld::<T>();
+    }
+
+    fn shrd<T: OpSized>(&mut self) {
+        self.asm.shrd::<T>();
+    }
+
     fn combine<T: OpSized>(&mut self) {
         self.asm.combine::<T>();
     }
@@ -491,8 +1580,16 @@ impl Asm for Virtualizer {
         self.asm.split::<T>();
     }
 
-    fn mul<T: OpSized>(&mut self) {
-        self.asm.mul::<T>();
+    fn mul<T: OpSized>(&mut self, signed: bool) {
+        if signed {
+            self.asm.imul::<T>();
+        } else {
+            self.asm.mul::<T>();
+        }
+    }
+
+    fn imul_trunc<T: OpSized>(&mut self) {
+        self.asm.imul_trunc::<T>();
     }
 
     fn and<T: OpSized>(&mut self) {
@@ -515,6 +1612,78 @@ impl Asm for Virtualizer {
         self.asm.cmp::<T>();
     }
 
+    fn test<T: OpSized>(&mut self) {
+        self.asm.test::<T>();
+    }
+
+    fn atomic<T: OpSized>(&mut self, op: AtomicOp) {
+        self.asm.atomic::<T>(op);
+    }
+
+    fn string<T: OpSized>(&mut self, op: StringOp) {
+        self.asm.string::<T>(op);
+    }
+
+    fn packed<T: OpSized>(&mut self, op: PackedOp) {
+        self.asm.packed::<T>(op);
+    }
+
+    fn pshufd(&mut self) {
+        self.asm.pshufd();
+    }
+
+    fn float<T: OpSized>(&mut self, op: FloatOp) {
+        self.asm.float::<T>(op);
+    }
+
+    fn bt<T: OpSized>(&mut self) {
+        self.asm.bt::<T>();
+    }
+
+    fn bts<T: OpSized>(&mut self) {
+        self.asm.bts::<T>();
+    }
+
+    fn btr<T: OpSized>(&mut self) {
+        self.asm.btr::<T>();
+    }
+
+    fn btc<T: OpSized>(&mut self) {
+        self.asm.btc::<T>();
+    }
+
+    fn bit_addr<T: OpSized>(&mut self) {
+        self.asm.bit_addr::<T>();
+    }
+
+    fn bsf<T: OpSized>(&mut self) {
+        self.asm.bsf::<T>();
+    }
+
+    fn bsr<T: OpSized>(&mut self) {
+        self.asm.bsr::<T>();
+    }
+
+    fn tzcnt<T: OpSized>(&mut self) {
+        self.asm.tzcnt::<T>();
+    }
+
+    fn lzcnt<T: OpSized>(&mut self) {
+        self.asm.lzcnt::<T>();
+    }
+
+    fn popcnt<T: OpSized>(&mut self) {
+        self.asm.popcnt::<T>();
+    }
+
+    fn bswap<T: OpSized>(&mut self) {
+        self.asm.bswap::<T>();
+    }
+
+    fn cond<T: OpSized>(&mut self, cond: JmpCond) {
+        self.asm.cond::<T>(cond);
+    }
+
     fn vmadd(&mut self) {
         self.asm.vmadd()
     }
@@ -535,9 +1704,17 @@ impl Asm for Virtualizer {
         self.asm.vmexit();
     }
 
+    fn vmret(&mut self) {
+        self.asm.vmret();
+    }
+
     fn load_operand(&mut self, inst: &Instruction, operand: u32) {
         match inst.op_kind(operand) {
             OpKind::Register => self.load_reg(inst.op_register(operand)),
+            OpKind::Memory if inst.memory_size().size() == 16 => {
+                self.lea_operand(inst);
+                self.asm.load_xmm();
+            }
             OpKind::Memory => {
                 self.lea_operand(inst);
                 vmasm_sized!(self, load, inst;);
@@ -561,6 +1738,10 @@ impl Asm for Virtualizer {
     fn store_operand(&mut self, inst: &Instruction, operand: u32) {
         match inst.op_kind(operand) {
             OpKind::Register => self.store_reg(inst.op_register(operand)),
+            OpKind::Memory if inst.memory_size().size() == 16 => {
+                self.lea_operand(inst);
+                self.asm.store_xmm();
+            }
             OpKind::Memory => {
                 self.lea_operand(inst);
                 vmasm_sized!(self, store, inst;);
@@ -578,14 +1759,7 @@ impl Asm for Virtualizer {
             let operand_size = OpSize::try_from(reg.size() as u8).unwrap();
 
             match operand_size {
-                OpSize::Byte => if reg.is_higher_8_bit() {
-                    // load 8 is same as 16 bit anyways it will get truncated
-                    self.asm.load::<u16>();
-                    // shift higher bits to lower on stack
-                    self.asm.rot_right();
-                } else {
-                    self.asm.load::<u8>()
-                },
+                OpSize::Byte => self.asm.load::<u8>(),
                 OpSize::Word => self.asm.load::<u16>(),
                 OpSize::Dword => self.asm.load::<u32>(),
                 OpSize::Qword => self.asm.load::<u64>()
@@ -605,14 +1779,7 @@ impl Asm for Virtualizer {
             let operand_size = OpSize::try_from(reg.size() as u8).unwrap();
 
             match operand_size {
-                OpSize::Byte => if reg.is_higher_8_bit() {
-                    self.asm.store_reg::<u8>();
-                    self.load_reg(reg.get_gpr_16());
-                    self.asm.rot_left();
-                    self.store_reg(reg.get_gpr_16());
-                } else {
-                    self.asm.store_reg::<u8>()
-                },
+                OpSize::Byte => self.asm.store_reg::<u8>(),
                 OpSize::Word => self.asm.store_reg::<u16>(),
                 OpSize::Dword => self.asm.store_reg::<u32>(),
                 OpSize::Qword => self.asm.store_reg::<u64>()
@@ -635,9 +1802,26 @@ impl Asm for Virtualizer {
         }
     }
 
+    fn load_rflags(&mut self) {
+        self.asm.vmctx();
+        self.asm.const_(offset_of!(Machine, rflags) as u64);
+        self.asm.vmadd();
+        self.asm.load::<u64>();
+    }
+
+    fn store_rflags(&mut self) {
+        self.asm.vmctx();
+        self.asm.const_(offset_of!(Machine, rflags) as u64);
+        self.asm.vmadd();
+        self.asm.store::<u64>();
+    }
+
     fn lea_operand(&mut self, inst: &Instruction) {
-        if inst.memory_base() != iced_x86::Register::None
-            && inst.memory_base() != iced_x86::Register::RIP {
+        // rip relative operands are a single relocated displacement
+        let has_base = inst.memory_base() != iced_x86::Register::None
+            && inst.memory_base() != iced_x86::Register::RIP;
+
+        if has_base {
             self.load_reg(inst.memory_base());
         }
 
@@ -646,21 +1830,34 @@ impl Asm for Virtualizer {
             self.asm.const_(inst.memory_index_scale() as u64);
             self.asm.vmmul();
 
-            if inst.memory_base() != iced_x86::Register::None {
+            if has_base {
                 self.asm.vmadd();
             }
         }
 
-        self.asm.const_(inst.memory_displacement64());
-
         if inst.memory_base() == iced_x86::Register::RIP {
-            self.asm.vmreloc(self.image_base);
+            self.native_address(inst.memory_displacement64());
+        } else {
+            self.asm.const_(inst.memory_displacement64());
         }
 
-        if inst.memory_base() != iced_x86::Register::None
-            || inst.memory_index() != iced_x86::Register::None {
+        if has_base || inst.memory_index() != iced_x86::Register::None {
             self.asm.vmadd();
         }
+
+        // lea only computes the offset into the segment
+        match inst.memory_segment() {
+            _ if inst.mnemonic() == Mnemonic::Lea => {}
+            iced_x86::Register::GS => {
+                self.asm.gs_base();
+                self.asm.vmadd();
+            }
+            iced_x86::Register::FS => {
+                self.asm.fs_base();
+                self.asm.vmadd();
+            }
+            _ => {}
+        }
     }
 }
 
@@ -155,39 +155,6 @@ impl HigherLower8Bit for iced_x86::Register {
     }
 }
 
-pub trait RegUp {
-    /// get 16 bit reg from 8 bit reg
-    fn get_gpr_16(self) -> Self;
-}
-
-impl RegUp for iced_x86::Register {
-    fn get_gpr_16(self) -> Self {
-        match self {
-            Self::AH => Self::AX,
-            Self::AL => Self::AX,
-            Self::BH => Self::BX,
-            Self::BL => Self::BX,
-            Self::CH => Self::CX,
-            Self::CL => Self::CX,
-            Self::DH => Self::DX,
-            Self::DL => Self::DX,
-            Self::SIL => Self::SI,
-            Self::DIL => Self::DI,
-            Self::SPL => Self::SP,
-            Self::BPL => Self::BP,
-            Self::R8L => Self::R8W,
-            Self::R9L => Self::R9W,
-            Self::R10L => Self::R10W,
-            Self::R11L => Self::R11W,
-            Self::R12L => Self::R12W,
-            Self::R13L => Self::R13W,
-            Self::R14L => Self::R14W,
-            Self::R15L => Self::R15W,
-            _ => Self::None
-        }
-    }
-}
-
 impl From<iced_x86::Mnemonic> for JmpCond {
     fn from(mnemonic: iced_x86::Mnemonic) -> Self {
         match mnemonic {
@@ -199,11 +166,46 @@ impl From<iced_x86::Mnemonic> for JmpCond {
             iced_x86::Mnemonic::Jae => JmpCond::Jae, // Jae is jnc
             iced_x86::Mnemonic::Jle => JmpCond::Jle, // jng is jle
             iced_x86::Mnemonic::Jg => JmpCond::Jg, // Jnle is jg
+            iced_x86::Mnemonic::Jb => JmpCond::Jb, // jc and jnae are jb
+            iced_x86::Mnemonic::Jl => JmpCond::Jl, // jnge is jl
+            iced_x86::Mnemonic::Jge => JmpCond::Jge, // jnl is jge
+            iced_x86::Mnemonic::Js => JmpCond::Js,
+            iced_x86::Mnemonic::Jns => JmpCond::Jns,
+            iced_x86::Mnemonic::Jo => JmpCond::Jo,
+            iced_x86::Mnemonic::Jno => JmpCond::Jno,
+            iced_x86::Mnemonic::Jp => JmpCond::Jp, // jpe is jp
+            iced_x86::Mnemonic::Jnp => JmpCond::Jnp, // jpo is jnp
+            iced_x86::Mnemonic::Jrcxz => JmpCond::Jrcxz,
+            iced_x86::Mnemonic::Jecxz => JmpCond::Jecxz,
             _ => panic!("unsupported jmp condition"),
         }
     }
 }
 
+impl From<iced_x86::ConditionCode> for JmpCond {
+    fn from(cc: iced_x86::ConditionCode) -> Self {
+        match cc {
+            iced_x86::ConditionCode::o => JmpCond::Jo,
+            iced_x86::ConditionCode::no => JmpCond::Jno,
+            iced_x86::ConditionCode::b => JmpCond::Jb,
+            iced_x86::ConditionCode::ae => JmpCond::Jae,
+            iced_x86::ConditionCode::e => JmpCond::Je,
+            iced_x86::ConditionCode::ne => JmpCond::Jne,
+            iced_x86::ConditionCode::be => JmpCond::Jbe,
+            iced_x86::ConditionCode::a => JmpCond::Ja,
+            iced_x86::ConditionCode::s => JmpCond::Js,
+            iced_x86::ConditionCode::ns => JmpCond::Jns,
+            iced_x86::ConditionCode::p => JmpCond::Jp,
+            iced_x86::ConditionCode::np => JmpCond::Jnp,
+            iced_x86::ConditionCode::l => JmpCond::Jl,
+            iced_x86::ConditionCode::ge => JmpCond::Jge,
+            iced_x86::ConditionCode::le => JmpCond::Jle,
+            iced_x86::ConditionCode::g => JmpCond::Jg,
+            _ => panic!("unsupported condition code"),
+        }
+    }
+}
+
 impl From<iced_x86::Register> for Register {
     fn from(reg: iced_x86::Register) -> Self {
         match reg {
@@ -309,7 +311,7 @@ pub trait MachineRegOffset {
 }
 
 impl MachineRegOffset for iced_x86::Register {
-    /// Get offset to reg in [Machine] struct
+    /// Get offset to reg in [Machine] struct, ah, bh, ch and dh point to the second byte of their register
     fn reg_offset(&self) -> u64 {
         if self.is_xmm() {
             offset_of!(Machine, fxsave) as u64 + memoffset::offset_of!(XSaveMin, xmm_registers) as u64
@@ -317,6 +319,7 @@ impl MachineRegOffset for iced_x86::Register {
         } else {
             offset_of!(Machine, regs) as u64
                 + u8::from(Register::from(*self)) as u64 * 8
+                + self.is_higher_8_bit() as u64
         }
     }
 }
\ No newline at end of file
@@ -69,6 +69,8 @@ fn virtualize_and_run(binary_name: &str, functions: Vec<String>, input: Option<&
     ).unwrap().with_map_file(format!("testbins\\{binary_name}\\target\\{binary_name}.map"));
     obfuscator.use_obfuscation(true);
     obfuscator.add_functions(functions).unwrap();
+    // the .pdata bounds only differ from the map file by int3 padding
+    assert!(obfuscator.bounds_mismatches().is_empty());
     obfuscator.virtualize().unwrap();
 
     run_binary(&format!("testbins\\{binary_name}\\target\\release\\{binary_name}_vrt.exe"), input)
@@ -0,0 +1,29 @@
+# Source of marked_fixture.exe, regions marked with the bytes guardian_begin!() and guardian_end!() of the sdk emit:
+#
+# llvm-mc -triple x86_64-pc-windows-msvc -filetype obj marked_fixture.s -o marked_fixture.obj
+# rust-lld -flavor link /entry:mainCRTStartup /subsystem:console /nodefaultlib /out:marked_fixture.exe \
+#     marked_fixture.obj
+
+    .intel_syntax noprefix
+    .text
+    .globl mainCRTStartup
+    .p2align 4
+mainCRTStartup:
+    mov eax, ecx
+    .byte 0xeb, 0x10
+    .ascii "GUARDIAN_BEGIN\0\0"
+    imul eax, eax, 3
+    add eax, 1
+    .byte 0xeb, 0x10
+    .ascii "GUARDIAN_END\0\0\0\0"
+    call check
+    ret
+
+    .p2align 4
+check:
+    .byte 0xeb, 0x10
+    .ascii "GUARDIAN_BEGIN\0\0"
+    xor eax, 0x1337
+    .byte 0xeb, 0x10
+    .ascii "GUARDIAN_END\0\0\0\0"
+    ret
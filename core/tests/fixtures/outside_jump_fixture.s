@@ -0,0 +1,23 @@
+# Source of outside_jump_fixture.exe, a marked region with code only reached by a jump from outside of it:
+#
+# llvm-mc -triple x86_64-pc-windows-msvc -filetype obj outside_jump_fixture.s -o outside_jump_fixture.obj
+# rust-lld -flavor link /entry:mainCRTStartup /subsystem:console /nodefaultlib /out:outside_jump_fixture.exe \
+#     outside_jump_fixture.obj
+
+    .intel_syntax noprefix
+    .text
+    .globl mainCRTStartup
+    .p2align 4
+mainCRTStartup:
+    test ecx, ecx
+    jz shared
+    .byte 0xeb, 0x10
+    .ascii "GUARDIAN_BEGIN\0\0"
+    imul eax, ecx, 3
+    jmp done
+shared:
+    mov eax, 1
+done:
+    .byte 0xeb, 0x10
+    .ascii "GUARDIAN_END\0\0\0\0"
+    ret
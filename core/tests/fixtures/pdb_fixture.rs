@@ -0,0 +1,44 @@
+// Source of pdb_fixture.exe and pdb_fixture.pdb, it doesn't need a windows toolchain to rebuild:
+//
+// rustc +nightly --target x86_64-pc-windows-msvc --crate-type bin --emit obj -C debuginfo=2 -C opt-level=0 \
+//     -C codegen-units=4 -C panic=abort pdb_fixture.rs --out-dir obj
+// rust-lld -flavor link /entry:mainCRTStartup /subsystem:console /nodefaultlib /debug /export:mainCRTStartup \
+//     /out:pdb_fixture.exe /pdb:pdb_fixture.pdb obj/*.o
+//
+// Multiple codegen units turn calls between modules into public symbols, `checksum` stays a private procedure.
+// The entry point is exported to have an export directory.
+
+#![feature(no_core, lang_items)]
+#![no_core]
+#![no_main]
+#![allow(internal_features)]
+
+#[lang = "pointee_sized"]
+pub trait PointeeSized {}
+#[lang = "meta_sized"]
+pub trait MetaSized: PointeeSized {}
+#[lang = "sized"]
+pub trait Sized: MetaSized {}
+#[lang = "copy"]
+pub trait Copy {}
+
+pub mod license {
+    fn checksum(key: u32) -> u32 {
+        key
+    }
+
+    pub fn check(key: u32) -> u32 {
+        checksum(key)
+    }
+}
+
+pub mod math {
+    pub fn calc(x: u32) -> u32 {
+        crate::license::check(x)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mainCRTStartup() -> u32 {
+    math::calc(7)
+}
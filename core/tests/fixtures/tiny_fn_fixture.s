@@ -0,0 +1,27 @@
+# Source of tiny_fn_fixture.exe, exported functions too small for the patch that enters the vm:
+#
+# llvm-mc -triple x86_64-pc-windows-msvc -filetype obj tiny_fn_fixture.s -o tiny_fn_fixture.obj
+# rust-lld -flavor link /entry:mainCRTStartup /subsystem:console /nodefaultlib /export:leaf /export:stub \
+#     /export:mainCRTStartup /out:tiny_fn_fixture.exe tiny_fn_fixture.obj
+#
+# None of them has unwind info, `leaf` is followed by int3 padding and `stub` runs right into the entry point.
+# lld puts the file name in front of the export tables, its length keeps them aligned for the exe crate.
+
+    .intel_syntax noprefix
+    .text
+    .globl leaf
+    .p2align 4
+leaf:
+    mov eax, 1
+    ret
+
+    .globl stub
+    .p2align 4, 0xcc
+stub:
+    xor eax, eax
+    .globl mainCRTStartup
+mainCRTStartup:
+    mov eax, ecx
+    imul eax, eax, 3
+    xor eax, 0x1337
+    ret
@@ -0,0 +1,45 @@
+use guardian::Obfuscator;
+use guardian::virtualizer::cfg::ControlFlowGraph;
+use guardian_sdk::END_MARKER;
+
+const MARKED_FIXTURE: &str = "tests/fixtures/marked_fixture.exe";
+const OUTSIDE_JUMP_FIXTURE: &str = "tests/fixtures/outside_jump_fixture.exe";
+
+#[test]
+fn marked_regions() {
+    let mut obfuscator = Obfuscator::new(MARKED_FIXTURE.to_owned(), String::new()).unwrap();
+
+    // the routines span both markers, they are erased along with the region
+    assert_eq!(obfuscator.add_marked_regions().unwrap(), 2);
+    assert_eq!(obfuscator.routines().collect::<Vec<_>>(), vec![0x1002..0x102c, 0x1040..0x1069]);
+
+    // the code between the markers and the jmp of the end marker, which leaves the vm after the marker
+    let fixture = std::fs::read(MARKED_FIXTURE).unwrap();
+    let region = &fixture[0x214..0x21c];
+    let cfg = ControlFlowGraph::recover(&[(0x1014, region)], |_, _| None).unwrap();
+    assert_eq!(cfg.instructions().count(), 3);
+    assert_eq!(cfg.tail_jumps, vec![(0x101a, 0x102c)]);
+}
+
+#[test]
+fn unbalanced_markers() {
+    let mut fixture = std::fs::read(MARKED_FIXTURE).unwrap();
+    let end = fixture.windows(END_MARKER.len()).position(|window| window == END_MARKER).unwrap();
+    fixture[end..end + END_MARKER.len()].fill(0x90);
+    let path = std::env::temp_dir().join("guardian_unbalanced_markers.exe");
+    std::fs::write(&path, fixture).unwrap();
+
+    let mut obfuscator = Obfuscator::new(path.to_str().unwrap().to_owned(), String::new()).unwrap();
+    let error = obfuscator.add_marked_regions().unwrap_err();
+    assert_eq!(error.to_string(), "begin marker at 0x1040 inside of the region starting at 0x1002, regions can't be \
+        nested");
+}
+
+#[test]
+fn code_reached_from_outside() {
+    // the mov between the markers is only reached by the jz in front of the begin marker
+    let mut obfuscator = Obfuscator::new(OUTSIDE_JUMP_FIXTURE.to_owned(), String::new()).unwrap();
+    let error = obfuscator.add_marked_regions().unwrap_err();
+    assert_eq!(error.to_string(), "code at 0x101b..0x1020 in the region at 0x1004 isn't reachable from its begin \
+        marker");
+}
@@ -0,0 +1,146 @@
+use guardian::pe::pdb_file::PdbFile;
+use guardian::pe::symbols::{FunctionSelector, FunctionSymbol, SymbolProvider};
+use guardian::Obfuscator;
+
+const PE_FIXTURE: &str = "tests/fixtures/pdb_fixture.exe";
+const PDB_FIXTURE: &str = "tests/fixtures/pdb_fixture.pdb";
+const TINY_FIXTURE: &str = "tests/fixtures/tiny_fn_fixture.exe";
+
+#[test]
+fn pdb_procedures() {
+    let pdb = PdbFile::load(PDB_FIXTURE).unwrap();
+
+    // global procedure with its public symbol
+    assert_eq!(pdb.function("pdb_fixture::license::check"), Some(FunctionSymbol {
+        name: "pdb_fixture::license::check".to_owned(),
+        rva: 0x1000,
+        size: 19,
+        exact: true,
+    }));
+    // private procedures have no public symbol
+    assert_eq!(pdb.function("pdb_fixture::license::checksum"), Some(FunctionSymbol {
+        name: "pdb_fixture::license::checksum".to_owned(),
+        rva: 0x1020,
+        size: 9,
+        exact: true,
+    }));
+    assert_eq!(pdb.function("pdb_fixture::mainCRTStartup").map(|function| function.rva), Some(0x1030));
+    assert_eq!(pdb.function("pdb_fixture::math::calc").map(|function| function.size), Some(19));
+    assert_eq!(pdb.function("pdb_fixture::checksum"), None);
+}
+
+#[test]
+fn pdb_public_symbols() {
+    let pdb = PdbFile::load(PDB_FIXTURE).unwrap();
+
+    // mangled and demangled public names resolve to the same procedure
+    let calc = pdb.function("pdb_fixture::math::calc").unwrap();
+    assert_eq!(pdb.function("_RNvNtCseoA3GBYLAyz_11pdb_fixture4math4calc"), Some(calc));
+    assert_eq!(pdb.function("mainCRTStartup").map(|function| function.rva), Some(0x1030));
+}
+
+#[test]
+fn exports_and_rva_ranges() {
+    let mut obfuscator = Obfuscator::new(PE_FIXTURE.to_owned(), String::new()).unwrap();
+
+    // the export ends with its .pdata entry
+    obfuscator.add_export("mainCRTStartup".to_owned()).unwrap();
+    assert!(obfuscator.add_export("missing".to_owned()).is_err());
+
+    obfuscator.add_rva_range(0x1000, 19).unwrap();
+    assert!(obfuscator.add_rva_range(0x2000, 0x10).is_err());
+    assert!(obfuscator.add_rva_range(0x1050, 0x20).is_err());
+    // ranges have to hold the patch that enters the vm
+    assert!(obfuscator.add_rva_range(0x1020, 9).is_err());
+    assert!(obfuscator.add_rva_range(0x1020, 0).is_err());
+    assert!(obfuscator.add_rva_range(0x1000, u32::MAX as usize).is_err());
+    assert!(obfuscator.add_rva_range(0x1000, 0x1_0000_0010).is_err());
+
+    // routines are patched once, exports passed again are skipped and overlapping ranges rejected
+    obfuscator.add_export("mainCRTStartup".to_owned()).unwrap();
+    assert!(obfuscator.add_rva_range(0x1000, 19).is_err());
+    assert!(obfuscator.add_rva_range(0x1040, 0x10).is_err());
+
+    assert_eq!(obfuscator.routines().collect::<Vec<_>>(), vec![0x1030..0x1044, 0x1000..0x1013]);
+
+    let mut obfuscator = Obfuscator::new(PE_FIXTURE.to_owned(), String::new()).unwrap()
+        .with_pdb(PDB_FIXTURE.to_owned());
+    obfuscator.add_function("pdb_fixture::math::calc".to_owned()).unwrap();
+    let error = obfuscator.add_rva_range(0x1050, 19).unwrap_err();
+    assert_eq!(error.to_string(), "rva range 0x1050..0x1063 overlaps the routine at 0x1050");
+}
+
+#[test]
+fn functions_too_small_to_patch() {
+    let mut obfuscator = Obfuscator::new(TINY_FIXTURE.to_owned(), String::new()).unwrap();
+
+    // leaf functions end before their int3 padding, which leaves too little code for the patch
+    let error = obfuscator.add_export("leaf".to_owned()).unwrap_err();
+    assert!(error.to_string().contains("too small to patch"), "{error}");
+    // exports end at the next export
+    let error = obfuscator.add_export("stub".to_owned()).unwrap_err();
+    assert!(error.to_string().contains("too small to patch"), "{error}");
+
+    obfuscator.add_export("mainCRTStartup".to_owned()).unwrap();
+    assert_eq!(obfuscator.routines().collect::<Vec<_>>(), vec![0x1012..0x101d]);
+}
+
+#[test]
+fn pdb_function_bounds() {
+    let mut obfuscator = Obfuscator::new(PE_FIXTURE.to_owned(), String::new()).unwrap()
+        .with_pdb(PDB_FIXTURE.to_owned());
+
+    obfuscator.add_functions(vec!["pdb_fixture::math::calc".to_owned(), "pdb_fixture::license::checksum".to_owned()])
+        .unwrap();
+    assert!(obfuscator.bounds_mismatches().is_empty());
+    assert_eq!(obfuscator.routines().collect::<Vec<_>>(), vec![0x1050..0x1063, 0x1020..0x1029]);
+}
+
+#[test]
+fn function_selectors() {
+    let pdb = PdbFile::load(PDB_FIXTURE).unwrap();
+    let rvas = |selector: &str| pdb.functions(&FunctionSelector::parse(selector).unwrap()).iter()
+        .map(|function| function.rva)
+        .collect::<Vec<_>>();
+
+    assert_eq!(rvas("pdb_fixture::license::*"), vec![0x1000, 0x1020]);
+    assert_eq!(rvas("*::check?um"), vec![0x1020]);
+    assert_eq!(rvas("re:^pdb_fixture::(math|license)::c"), vec![0x1000, 0x1020, 0x1050]);
+    assert_eq!(rvas("_RNvNtCseoA3GBYLAyz_11pdb_fixture4math4calc"), vec![0x1050]);
+    // exact names match the whole name, globs the whole name or the end of the path
+    assert_eq!(rvas("pdb_fixture::license"), Vec::<u32>::new());
+    assert_eq!(rvas("license::*"), vec![0x1000, 0x1020]);
+    assert_eq!(rvas("icense::*"), Vec::<u32>::new());
+    assert!(FunctionSelector::parse("re:(").is_err());
+}
+
+#[test]
+fn exact_names_before_globs() {
+    let names = ["?check@license@@YA_NXZ", "?chock@license@@YA_NXZ", "Foo::operator*", "Foo::operator*=",
+        "Foo::operator->*", "Foo::operator=="];
+    let select = |selector: &str| FunctionSelector::parse(selector).unwrap()
+        .select(|matches| names.iter().copied().filter(|name| matches(name)).collect());
+
+    // msvc mangled names start with ? and operators contain *, an exact name isn't read as a glob
+    assert_eq!(select("?check@license@@YA_NXZ"), vec!["?check@license@@YA_NXZ"]);
+    assert_eq!(select("Foo::operator*"), vec!["Foo::operator*"]);
+    // globs apply when no name is equal
+    assert_eq!(select("?ch?ck@license@@YA_NXZ"), vec!["?check@license@@YA_NXZ", "?chock@license@@YA_NXZ"]);
+    assert_eq!(select("Foo::operator?="), vec!["Foo::operator*=", "Foo::operator=="]);
+}
+
+#[test]
+fn excluded_functions() {
+    let mut obfuscator = Obfuscator::new(PE_FIXTURE.to_owned(), String::new()).unwrap()
+        .with_pdb(PDB_FIXTURE.to_owned());
+
+    obfuscator.exclude("*checksum").unwrap();
+    let matches = obfuscator.add_function("pdb_fixture::license::*".to_owned()).unwrap();
+    assert_eq!(matches.iter().map(|function| function.name.as_str()).collect::<Vec<_>>(),
+        vec!["pdb_fixture::license::check"]);
+    assert!(obfuscator.add_function("pdb_fixture::license::checksum".to_owned()).is_err());
+
+    // functions matched again by another selector are only added once
+    obfuscator.add_function("re:check$".to_owned()).unwrap();
+    assert_eq!(obfuscator.routines().collect::<Vec<_>>(), vec![0x1000..0x1013]);
+}
@@ -0,0 +1,84 @@
+// guardian-rs
+// Copyright (C) 2023 felix-rs
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Markers for virtualizing a region inside of a function instead of the whole function
+//!
+//! ```ignore
+//! fn main() {
+//!     let key = read_key();
+//!     guardian_sdk::guardian_begin!();
+//!     let valid = check_license(&key);
+//!     guardian_sdk::guardian_end!();
+//!     println!("{valid}");
+//! }
+//! ```
+//!
+//! The obfuscator enters the vm at the begin marker and leaves it right after the end marker, both markers
+//! are erased. Unprotected builds jump over the markers and run as usual. All code the compiler places
+//! between the markers has to be reachable from the begin marker, regions it branches into from outside
+//! are rejected.
+
+#![no_std]
+
+/// `jmp short` over the signature that follows it
+pub const MARKER_JMP: [u8; 2] = [0xeb, 0x10];
+
+/// Bytes emitted by [`guardian_begin!`]
+pub const BEGIN_MARKER: [u8; 18] = marker(*b"GUARDIAN_BEGIN\0\0");
+
+/// Bytes emitted by [`guardian_end!`]
+pub const END_MARKER: [u8; 18] = marker(*b"GUARDIAN_END\0\0\0\0");
+
+const fn marker(signature: [u8; 16]) -> [u8; 18] {
+    let mut marker = [0u8; 18];
+    marker[0] = MARKER_JMP[0];
+    marker[1] = MARKER_JMP[1];
+    let mut i = 0;
+    while i < signature.len() {
+        marker[i + 2] = signature[i];
+        i += 1;
+    }
+    marker
+}
+
+/// Starts a region to virtualize, has to be followed by [`guardian_end!`] in the same function
+#[macro_export]
+macro_rules! guardian_begin {
+    () => {
+        // not nomem, memory accesses must not be moved across the marker
+        unsafe {
+            ::core::arch::asm!(
+                ".byte 0xeb, 0x10",
+                ".ascii \"GUARDIAN_BEGIN\\0\\0\"",
+                options(nostack, preserves_flags),
+            )
+        }
+    };
+}
+
+/// Ends the region started by [`guardian_begin!`], the vm is left right after it
+#[macro_export]
+macro_rules! guardian_end {
+    () => {
+        unsafe {
+            ::core::arch::asm!(
+                ".byte 0xeb, 0x10",
+                ".ascii \"GUARDIAN_END\\0\\0\\0\\0\"",
+                options(nostack, preserves_flags),
+            )
+        }
+    };
+}
@@ -0,0 +1,25 @@
+use guardian_sdk::{BEGIN_MARKER, END_MARKER, guardian_begin, guardian_end};
+
+#[inline(never)]
+fn marked(x: u64) -> u64 {
+    let mut x = std::hint::black_box(x);
+    guardian_begin!();
+    x = x.wrapping_mul(3) + 1;
+    guardian_end!();
+    x
+}
+
+#[test]
+fn markers_are_emitted() {
+    let code = unsafe { std::slice::from_raw_parts(marked as *const u8, 0x100) };
+    let find = |marker: &[u8]| code.windows(marker.len()).position(|window| window == marker);
+
+    let begin = find(&BEGIN_MARKER).unwrap();
+    let end = find(&END_MARKER).unwrap();
+    assert!(begin < end);
+}
+
+#[test]
+fn markers_are_skipped() {
+    assert_eq!(marked(5), 16);
+}
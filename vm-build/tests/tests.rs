@@ -4,6 +4,22 @@ mod tests {
     use guardian::virtualizer::virtualize;
     use guardian_vm::Machine;
 
+    /// Assembles `body` followed by a `ret` and virtualizes it
+    #[cfg(target_env = "msvc")]
+    fn virtualize_body(body: impl FnOnce(&mut iced_x86::code_asm::CodeAssembler)) -> Vec<u8> {
+        let mut a = iced_x86::code_asm::CodeAssembler::new(64).unwrap();
+        body(&mut a);
+        a.ret().unwrap();
+        virtualize(&a.assemble(0).unwrap()).unwrap()
+    }
+
+    /// Enters a fresh machine running `bytecode` and hands its entry point typed as `F` to `call`
+    #[cfg(target_env = "msvc")]
+    fn enter<F: Copy, R>(bytecode: &[u8], call: impl FnOnce(F) -> R) -> R {
+        let m = Machine::new(bytecode.as_ptr());
+        call(unsafe { std::mem::transmute_copy(&m.vmenter) })
+    }
+
     #[test]
     #[cfg(target_env = "msvc")]
     fn rax_and_eax() {
@@ -68,6 +84,227 @@ mod tests {
         assert_eq!(test, u128::MAX);
     }
 
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_sse_moves() {
+        use iced_x86::code_asm::*;
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.movups(xmm0, xmmword_ptr(rcx)).unwrap();
+        a.movaps(xmm1, xmm0).unwrap();
+        a.movdqu(xmmword_ptr(rdx), xmm1).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(&u128, &mut u128) = unsafe { std::mem::transmute(m.vmenter) };
+        let mut dst = 0;
+        f(&0x0011_2233_4455_6677_8899_aabb_ccdd_eeff, &mut dst);
+        assert_eq!(dst, 0x0011_2233_4455_6677_8899_aabb_ccdd_eeff);
+
+        // gpr and memory sources zero the upper lanes, movss and movsd between registers keep them
+        let sse = |op: fn(&mut CodeAssembler)| {
+            let bytecode = virtualize_body(|a| {
+                a.movdqu(xmm0, xmmword_ptr(rcx)).unwrap();
+                a.movdqu(xmm1, xmmword_ptr(rdx)).unwrap();
+                op(a);
+                a.movdqu(xmmword_ptr(rcx), xmm0).unwrap();
+            });
+            move |mut dst: u128, src: u128, gpr: u64| enter(&bytecode, |f: extern "C" fn(&mut u128, &u128, u64) -> u64| {
+                let result = f(&mut dst, &src, gpr);
+                (dst, result)
+            })
+        };
+
+        let ones = u128::MAX;
+        let src = 0x1111_2222_3333_4444_5555_6666_7777_8888;
+        assert_eq!(sse(|a| a.movq(xmm0, r8).unwrap())(ones, src, 0x1234).0, 0x1234);
+        assert_eq!(sse(|a| a.movd(xmm0, r8d).unwrap())(ones, src, u64::MAX).0, 0xffff_ffff);
+        assert_eq!(sse(|a| a.movq(xmm0, xmm1).unwrap())(ones, src, 0).0, 0x5555_6666_7777_8888);
+        assert_eq!(sse(|a| a.movq(xmm0, qword_ptr(rdx)).unwrap())(ones, src, 0).0, 0x5555_6666_7777_8888);
+        assert_eq!(sse(|a| a.movss(xmm0, xmm1).unwrap())(ones, src, 0).0, 0xffff_ffff_ffff_ffff_ffff_ffff_7777_8888);
+        assert_eq!(sse(|a| a.movss(xmm0, dword_ptr(rdx + 4)).unwrap())(ones, src, 0).0, 0x5555_6666);
+        assert_eq!(sse(|a| a.movsd_2(xmm0, xmm1).unwrap())(ones, src, 0).0, 0xffff_ffff_ffff_ffff_5555_6666_7777_8888);
+        assert_eq!(sse(|a| a.movsd_2(xmm0, qword_ptr(rdx + 8)).unwrap())(ones, src, 0).0, 0x1111_2222_3333_4444);
+
+        // stores only write the low lanes
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.movdqu(xmm1, xmmword_ptr(rdx)).unwrap();
+        a.movd(dword_ptr(rcx + 4), xmm1).unwrap();
+        a.movsd_2(qword_ptr(rcx + 8), xmm1).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(&mut u128, &u128) = unsafe { std::mem::transmute(m.vmenter) };
+        let mut dst = ones;
+        f(&mut dst, &src);
+        assert_eq!(dst, 0x5555_6666_7777_8888_7777_8888_ffff_ffff);
+
+        // gpr writes zero extend
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, -1i64).unwrap();
+        a.movq(xmm0, rcx).unwrap();
+        a.movd(eax, xmm0).unwrap();
+        a.movq(rdx, xmm0).unwrap();
+        a.add(rax, rdx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0x1_0000_0002), 0x1_0000_0004);
+
+        // gs movsd and addr32 movsd are string moves that aren't lifted, they go through vmexec
+        assert!(virtualize(&[0x65, 0xa5, 0xc3]).is_ok());
+        assert!(virtualize(&[0x67, 0xa5, 0xc3]).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_packed_integer() {
+        use iced_x86::code_asm::*;
+        let packed = |op: fn(&mut CodeAssembler)| {
+            let bytecode = virtualize_body(|a| {
+                a.movdqu(xmm0, xmmword_ptr(rcx)).unwrap();
+                a.movdqu(xmm1, xmmword_ptr(rdx)).unwrap();
+                op(a);
+                a.movdqu(xmmword_ptr(rcx), xmm0).unwrap();
+            });
+            move |mut dst: u128, src: u128| enter(&bytecode, |f: extern "C" fn(&mut u128, &u128)| {
+                f(&mut dst, &src);
+                dst
+            })
+        };
+
+        let a = 0x7fff_ffff_0000_0001_ffff_ffff_8000_00ff;
+        let b = 0x0000_0001_ffff_ffff_0000_0001_8000_0001;
+        assert_eq!(packed(|a| a.paddb(xmm0, xmm1).unwrap())(a, b), 0x7fff_ff00_ffff_ff00_ffff_ff00_0000_0000);
+        assert_eq!(packed(|a| a.paddw(xmm0, xmm1).unwrap())(a, b), 0x7fff_0000_ffff_0000_ffff_0000_0000_0100);
+        assert_eq!(packed(|a| a.paddd(xmm0, xmm1).unwrap())(a, b), 0x8000_0000_0000_0000_0000_0000_0000_0100);
+        assert_eq!(packed(|a| a.paddq(xmm0, xmmword_ptr(rdx)).unwrap())(a, b), 0x8000_0001_0000_0000_0000_0001_0000_0100);
+        assert_eq!(packed(|a| a.psubb(xmm0, xmm1).unwrap())(a, b), 0x7fff_fffe_0101_0102_ffff_fffe_0000_00fe);
+        assert_eq!(packed(|a| a.psubd(xmm0, xmm1).unwrap())(a, b), 0x7fff_fffe_0000_0002_ffff_fffe_0000_00fe);
+        assert_eq!(packed(|a| a.psubq(xmm0, xmm1).unwrap())(a, b), 0x7fff_fffd_0000_0002_ffff_fffe_0000_00fe);
+        assert_eq!(packed(|a| a.pand(xmm0, xmm1).unwrap())(a, b), a & b);
+        assert_eq!(packed(|a| a.pandn(xmm0, xmm1).unwrap())(a, b), !a & b);
+        assert_eq!(packed(|a| a.por(xmm0, xmm1).unwrap())(a, b), a | b);
+        assert_eq!(packed(|a| a.pxor(xmm0, xmm0).unwrap())(a, b), 0);
+        assert_eq!(packed(|a| a.pcmpeqb(xmm0, xmm1).unwrap())(a, b), 0x0000_0000_0000_0000_0000_0000_ffff_ff00);
+        assert_eq!(packed(|a| a.pcmpeqw(xmm0, xmm1).unwrap())(a, b), 0x0000_0000_0000_0000_0000_0000_ffff_0000);
+        assert_eq!(packed(|a| a.pcmpeqd(xmm0, xmm0).unwrap())(a, b), u128::MAX);
+        assert_eq!(packed(|a| a.pshufd(xmm0, xmm1, 0b00_01_10_11).unwrap())(a, b), 0x8000_0001_0000_0001_ffff_ffff_0000_0001);
+        assert_eq!(packed(|a| a.pshufd(xmm0, xmmword_ptr(rcx), 0).unwrap())(a, b), 0x8000_00ff_8000_00ff_8000_00ff_8000_00ff);
+
+        // strlen of a 16 byte block, pmovmskb still goes through vmexec
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.pxor(xmm0, xmm0).unwrap();
+        a.pcmpeqb(xmm0, xmmword_ptr(rcx)).unwrap();
+        a.pmovmskb(eax, xmm0).unwrap();
+        a.bsf(eax, eax).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(&[u8; 16]) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(b"hello, world!\0\0\0"), 13);
+        assert_eq!(f(b"\0hello, world!\0\0"), 0);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_scalar_float() {
+        use iced_x86::code_asm::*;
+        let scalar = |op: fn(&mut CodeAssembler)| {
+            let bytecode = virtualize_body(op);
+            move |x: f64, y: f64| enter(&bytecode, |f: extern "C" fn(f64, f64) -> f64| f(x, y))
+        };
+
+        assert_eq!(scalar(|a| a.addsd(xmm0, xmm1).unwrap())(1.5, 2.25), 3.75);
+        assert_eq!(scalar(|a| a.subsd(xmm0, xmm1).unwrap())(1.5, 2.25), -0.75);
+        assert_eq!(scalar(|a| a.mulsd(xmm0, xmm1).unwrap())(1.5, 2.25), 3.375);
+        assert_eq!(scalar(|a| a.divsd(xmm0, xmm1).unwrap())(1.0, 4.0), 0.25);
+        assert_eq!(scalar(|a| a.minsd(xmm0, xmm1).unwrap())(1.5, -2.0), -2.0);
+        assert_eq!(scalar(|a| a.maxsd(xmm0, xmm1).unwrap())(1.5, -2.0), 1.5);
+        assert_eq!(scalar(|a| a.sqrtsd(xmm0, xmm1).unwrap())(1.5, 16.0), 4.0);
+        assert_eq!(scalar(|a| { a.cvtsd2ss(xmm0, xmm0).unwrap(); a.cvtss2sd(xmm0, xmm0).unwrap(); })(0.1, 0.0),
+            0.1f32 as f64);
+
+        // single precision only writes the low dword
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.movdqu(xmm0, xmmword_ptr(rcx)).unwrap();
+        a.addss(xmm0, dword_ptr(rdx)).unwrap();
+        a.mulss(xmm0, xmm0).unwrap();
+        a.movdqu(xmmword_ptr(rcx), xmm0).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(&mut u128, &f32) = unsafe { std::mem::transmute(m.vmenter) };
+        let mut dst = 0x1111_2222_3333_4444_5555_6666_0000_0000 | 1.5f32.to_bits() as u128;
+        f(&mut dst, &0.5);
+        assert_eq!(dst, 0x1111_2222_3333_4444_5555_6666_0000_0000 | 4.0f32.to_bits() as u128);
+
+        // ucomisd and comiss clear of, sf and af, unordered operands set zf, pf and cf
+        let compare = |op: fn(&mut CodeAssembler)| {
+            let bytecode = virtualize_body(|a| {
+                a.or(eax, -1).unwrap();
+                op(a);
+                a.pushfq().unwrap();
+                a.pop(rax).unwrap();
+                a.and(eax, 0x8d5).unwrap();
+            });
+            move |x: f64, y: f64| enter(&bytecode, |f: extern "C" fn(f64, f64) -> u64| f(x, y))
+        };
+
+        let ucomisd = compare(|a| a.ucomisd(xmm0, xmm1).unwrap());
+        assert_eq!(ucomisd(1.0, 2.0), 0x1);
+        assert_eq!(ucomisd(2.0, 2.0), 0x40);
+        assert_eq!(ucomisd(3.0, 2.0), 0);
+        assert_eq!(ucomisd(f64::NAN, 2.0), 0x45);
+        let comiss = compare(|a| {
+            a.cvtsd2ss(xmm0, xmm0).unwrap();
+            a.cvtsd2ss(xmm1, xmm1).unwrap();
+            a.comiss(xmm0, xmm1).unwrap();
+        });
+        assert_eq!(comiss(-1.0, 2.0), 0x1);
+        assert_eq!(comiss(f64::NAN, f64::NAN), 0x45);
+
+        // int conversions, cvtsd2si rounds with the guest mxcsr while cvttsd2si truncates
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.sub(rsp, 8).unwrap();
+        a.stmxcsr(dword_ptr(rsp)).unwrap();
+        a.ldmxcsr(dword_ptr(rcx)).unwrap();
+        a.cvtsd2si(rax, xmm1).unwrap();
+        a.cvttsd2si(edx, xmm1).unwrap();
+        a.shl(rax, 8).unwrap();
+        a.add(rax, rdx).unwrap();
+        a.ldmxcsr(dword_ptr(rsp)).unwrap();
+        a.add(rsp, 8).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(&u32, f64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        let (nearest, down, up) = (0x1f80, 0x3f80, 0x5f80);
+        assert_eq!(f(&nearest, 2.5), 2 << 8 | 2);
+        assert_eq!(f(&nearest, 3.5), 4 << 8 | 3);
+        assert_eq!(f(&down, -1.5), (-2 << 8) + 0xffff_ffff);
+        assert_eq!(f(&up, 1.25), 2 << 8 | 1);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.cvtsi2sd(xmm0, rcx).unwrap();
+        a.cvtsi2ss(xmm1, edx).unwrap();
+        a.cvtss2sd(xmm1, xmm1).unwrap();
+        a.addsd(xmm0, xmm1).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(i64, i32) -> f64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(-3, 7), 4.0);
+        assert_eq!(f(1 << 40, -1), (1u64 << 40) as f64 - 1.0);
+    }
+
     #[test]
     #[cfg(target_env = "msvc")]
     fn inc_and_dec() {
@@ -93,6 +330,153 @@ mod tests {
         assert_eq!(f(1), 0);
     }
 
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_rflags() {
+        use iced_x86::code_asm::*;
+        // sets af and cf before the op, returns of, sf, zf, af, pf and cf afterwards
+        let flags = |op: fn(&mut CodeAssembler)| {
+            let bytecode = virtualize_body(|a| {
+                a.mov(al, 0xff).unwrap();
+                a.add(al, 1).unwrap();
+                op(a);
+                a.pushfq().unwrap();
+                a.pop(rax).unwrap();
+                a.and(eax, 0x8d5).unwrap();
+            });
+            move |x: u64, y: u64| enter(&bytecode, |f: extern "C" fn(u64, u64) -> u64| f(x, y))
+        };
+
+        // af is the carry or borrow out of bit 3
+        assert_eq!(flags(|a| a.add(cl, dl).unwrap())(0xf, 1), 0x10);
+        assert_eq!(flags(|a| a.sub(cl, dl).unwrap())(0x10, 1), 0x14);
+        assert_eq!(flags(|a| a.cmp(ecx, edx).unwrap())(0x20, 0x20), 0x44);
+        assert_eq!(flags(|a| a.adc(cx, dx).unwrap())(0xfff7, 8), 0x55);
+        // logical instructions clear af
+        assert_eq!(flags(|a| a.and(ecx, edx).unwrap())(0xff, 0xff), 0x4);
+        assert_eq!(flags(|a| a.xor(rcx, rdx).unwrap())(1, 1), 0x44);
+
+        // inc and dec keep cf
+        assert_eq!(flags(|a| a.inc(ecx).unwrap())(0x7fff_ffff, 0), 0x895);
+        assert_eq!(flags(|a| { a.clc().unwrap(); a.dec(ecx).unwrap(); })(0, 0), 0x94);
+        assert_eq!(flags(|a| a.dec(cl).unwrap())(0, 0), 0x95);
+
+        // popfq and sahf only write the flags they can change, lahf reads them back
+        assert_eq!(flags(|a| { a.push(rcx).unwrap(); a.popfq().unwrap(); })(0x8d5, 0), 0x8d5);
+        assert_eq!(flags(|a| { a.push(rcx).unwrap(); a.popfq().unwrap(); })(0, 0), 0);
+        assert_eq!(flags(|a| { a.mov(ah, cl).unwrap(); a.sahf().unwrap(); })(0xff, 0), 0xd5);
+        assert_eq!(flags(|a| {
+            a.cmp(cl, dl).unwrap();
+            a.mov(ah, 0xff).unwrap();
+            a.sahf().unwrap();
+        })(0x80, 1), 0x8d5);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(ah, 0xff).unwrap();
+        a.sahf().unwrap();
+        a.xor(eax, eax).unwrap();
+        a.lahf().unwrap();
+        a.mov(cl, ah).unwrap();
+        a.pushfq().unwrap();
+        a.pop(rax).unwrap();
+        a.mov(al, cl).unwrap();
+        a.and(eax, 0x2ff).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn() -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        // xor leaves only zf and pf, bit 1 is always set and if stays on
+        assert_eq!(f(), 0x246);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_segment_override() {
+        use iced_x86::code_asm::*;
+        let (teb, stack_base, user_pointer): (u64, u64, u64);
+        unsafe {
+            std::arch::asm!("mov {}, gs:[0x30]", "mov {}, gs:[0x8]", "mov {}, gs:[0x28]",
+                out(reg) teb, out(reg) stack_base, out(reg) user_pointer);
+        }
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, qword_ptr(0x30).gs()).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn() -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(), teb);
+
+        // base and index are offsets into the segment, lea ignores the segment base
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, qword_ptr(rcx + rdx * 2).gs()).unwrap();
+        a.lea(rdx, ptr(rcx + 0x10).gs()).unwrap();
+        a.mov(qword_ptr(r8), rdx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64, &mut u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        let mut offset = 0;
+        assert_eq!(f(4, 2, &mut offset), stack_base);
+        assert_eq!(offset, 0x14);
+
+        // stores and read modify writes, ArbitraryUserPointer is free to use
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(qword_ptr(0x28).gs(), rcx).unwrap();
+        a.add(qword_ptr(0x28).gs(), 1).unwrap();
+        a.mov(rax, qword_ptr(0x28).gs()).unwrap();
+        a.mov(qword_ptr(0x28).gs(), rdx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0x1234, user_pointer), 0x1235);
+        let restored: u64;
+        unsafe { std::arch::asm!("mov {}, gs:[0x28]", out(reg) restored); }
+        assert_eq!(restored, user_pointer);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_fs_segment() {
+        use guardian::virtualizer::Virtualizer;
+        use iced_x86::code_asm::*;
+        let fs_base: u64;
+        unsafe { std::arch::asm!("rdfsbase {}", out(reg) fs_base); }
+
+        // rcx is the offset of value into the fs segment
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, qword_ptr(rcx).fs()).unwrap();
+        a.add(qword_ptr(rcx).fs(), rdx).unwrap();
+        a.ret().unwrap();
+        let program = a.assemble(0).unwrap();
+
+        // lifted with fsgsbase and executed natively without it
+        for bytecode in [Virtualizer::new().with_fsgsbase(true).virtualize(&program).unwrap(), virtualize(&program).unwrap()] {
+            let m = Machine::new(bytecode.as_ptr());
+            let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+            let mut value = 0x1234u64;
+            assert_eq!(f((&mut value as *mut u64 as u64).wrapping_sub(fs_base), 1), 0x1234);
+            assert_eq!(value, 0x1235);
+        }
+
+        // natively executed branches wouldn't return to the vm
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.jmp(qword_ptr(rcx).fs()).unwrap();
+        let error = virtualize(&a.assemble(0).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("unsupported jmp"), "{error}");
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.call(qword_ptr(rcx).fs()).unwrap();
+        a.ret().unwrap();
+        let error = virtualize(&a.assemble(0).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("unsupported jmp"), "{error}");
+    }
+
     #[test]
     #[cfg(target_env = "msvc")]
     fn rax_and_ax() {
@@ -188,6 +572,127 @@ mod tests {
         assert_eq!(f(-2, 0), -3);
     }
 
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_jcc() {
+        use iced_x86::code_asm::*;
+        let conditions: &[(fn(&mut CodeAssembler, CodeLabel) -> Result<(), IcedError>, i64, i64, bool)] = &[
+            (|a, l| a.jl(l), -1, 0, true),
+            (|a, l| a.jl(l), 0, -1, false),
+            (|a, l| a.jge(l), 5, 5, true),
+            (|a, l| a.jb(l), 0, -1, true),
+            (|a, l| a.jb(l), -1, 0, false),
+            (|a, l| a.js(l), 0, 1, true),
+            (|a, l| a.jns(l), 0, 1, false),
+            (|a, l| a.jo(l), 0, 1, false),
+            (|a, l| a.jo(l), i64::MIN, 1, true),
+            (|a, l| a.jno(l), i64::MIN, 1, false),
+            (|a, l| a.jp(l), 3, 0, true),
+            (|a, l| a.jnp(l), 3, 0, false),
+        ];
+
+        for (jcc, op1, op2, taken) in conditions {
+            let mut a = CodeAssembler::new(64).unwrap();
+            let mut lbl = a.create_label();
+            a.xor(eax, eax).unwrap();
+            a.cmp(rcx, rdx).unwrap();
+            jcc(&mut a, lbl).unwrap();
+            a.mov(eax, 1).unwrap();
+            a.set_label(&mut lbl).unwrap();
+            a.ret().unwrap();
+
+            let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+            let m = Machine::new(bytecode.as_ptr());
+            let f: extern "C" fn(i64, i64) -> i32 = unsafe { std::mem::transmute(m.vmenter) };
+            assert_eq!(f(*op1, *op2) == 0, *taken, "cmp {op1}, {op2}");
+        }
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut lbl = a.create_label();
+        a.xor(eax, eax).unwrap();
+        a.jrcxz(lbl).unwrap();
+        a.mov(eax, 1).unwrap();
+        a.set_label(&mut lbl).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64) -> i32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0), 0);
+        assert_eq!(f(1 << 32), 1);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_setcc_cmovcc() {
+        use iced_x86::code_asm::*;
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, -1i64).unwrap();
+        a.cmp(rcx, rdx).unwrap();
+        a.setl(al).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(i64, i64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(-5, 3), -256 | 1);
+        assert_eq!(f(3, -5), -256);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut lbl = a.create_label();
+        a.mov(rax, -1i64).unwrap();
+        a.cmp(rcx, rdx).unwrap();
+        a.cmova(eax, ecx).unwrap();
+        // flags must survive the cmov
+        a.jbe(lbl).unwrap();
+        a.add(eax, 1).unwrap();
+        a.set_label(&mut lbl).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(10, 3), 11);
+        // 32 bit cmov zero extends even if the condition is false
+        assert_eq!(f(3, 10), 0xFFFFFFFF);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_test_and_logic_flags() {
+        use iced_x86::code_asm::*;
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut lbl = a.create_label();
+        a.mov(eax, 1).unwrap();
+        a.test(ecx, ecx).unwrap();
+        a.je(lbl).unwrap();
+        a.mov(eax, 2).unwrap();
+        a.set_label(&mut lbl).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u32) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0), 1);
+        assert_eq!(f(5), 2);
+
+        // and clears the cf set by cmp
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut lbl = a.create_label();
+        a.xor(eax, eax).unwrap();
+        a.cmp(ecx, edx).unwrap();
+        a.and(ecx, edx).unwrap();
+        a.jb(lbl).unwrap();
+        a.mov(eax, 1).unwrap();
+        a.set_label(&mut lbl).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u32, u32) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(1, 2), 1);
+    }
+
     #[test]
     #[cfg(target_env = "msvc")]
     fn virtualize_calc_lbl() {
@@ -224,76 +729,1039 @@ mod tests {
 
     #[test]
     #[cfg(target_env = "msvc")]
-    fn virtualize_div() {
+    fn virtualize_adc_sbb_neg() {
         use iced_x86::code_asm::*;
+        // 128 bit add, lo in rcx:rdx, hi in r8:r9
         let mut a = CodeAssembler::new(64).unwrap();
-        let mut remainder = 0;
-        a.mov(eax, 10).unwrap();
-        a.mov(r8, 8i64).unwrap();
-        a.xor(edx, edx).unwrap();
-        a.div(r8).unwrap(); // mov second argument to rcx (divisor)
-        a.mov(dword_ptr(rcx), edx).unwrap();
+        a.add(rcx, rdx).unwrap();
+        a.adc(r8, r9).unwrap();
+        a.mov(rax, r8).unwrap();
         a.ret().unwrap();
 
         let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
         let m = Machine::new(bytecode.as_ptr());
-        let f: extern "C" fn(&mut u32) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
-        assert_eq!(f(&mut remainder), 1);
-        assert_eq!(remainder, 2);
+        let f: extern "C" fn(u64, u64, u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(u64::MAX, 1, 5, 6), 12);
+        assert_eq!(f(u64::MAX, 0, 5, 6), 11);
+        // carry out of adc with op2 + carry wrapping around
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.add(rcx, rdx).unwrap();
+        a.adc(r8, r9).unwrap();
+        a.sbb(rax, rax).unwrap();
+        a.ret().unwrap();
 
-        // idiv
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64, u64, u64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(u64::MAX, 1, 5, u64::MAX), -1);
+        assert_eq!(f(u64::MAX, 0, 5, u64::MAX), -1);
+        assert_eq!(f(1, 0, 0, u64::MAX), 0);
+
+        // 128 bit sub
         let mut a = CodeAssembler::new(64).unwrap();
-        let mut remainder = 0;
-        a.mov(eax, 4294967278u32).unwrap();
-        a.mov(r8d, 6i32).unwrap();
-        a.cdq().unwrap();
-        a.idiv(r8d).unwrap(); // mov second argument to rcx (divisor)
-        a.mov(dword_ptr(rcx), edx).unwrap();
+        a.sub(ecx, edx).unwrap();
+        a.sbb(r8d, r9d).unwrap();
+        a.mov(eax, r8d).unwrap();
         a.ret().unwrap();
 
         let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
         let m = Machine::new(bytecode.as_ptr());
-        let f: extern "C" fn(&mut i32) -> i32 = unsafe { std::mem::transmute(m.vmenter) };
-        assert_eq!(f(&mut remainder), -3);
-        assert_eq!(remainder, 0);
+        let f: extern "C" fn(u32, u32, u32, u32) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0, 1, 5, 2), 2);
+        assert_eq!(f(1, 1, 5, 2), 3);
+
+        // abs via neg, cf is set for non zero operands
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, rcx).unwrap();
+        a.neg(rax).unwrap();
+        a.cmovl(rax, rcx).unwrap();
+        a.adc(rax, 0).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(-7), 8);
+        assert_eq!(f(7), 8);
+        assert_eq!(f(0), 0);
     }
 
     #[test]
     #[cfg(target_env = "msvc")]
-    fn virtualize_mul() {
+    fn virtualize_sign_extension() {
         use iced_x86::code_asm::*;
         let mut a = CodeAssembler::new(64).unwrap();
-        let mut higher_bits = 0u32;
-        a.mov(eax, 3).unwrap();
-        a.mul(rcx).unwrap();
-        a.mov(r8, &mut higher_bits as *mut _ as u64).unwrap();
-        a.mov(dword_ptr(r8), edx).unwrap();
+        a.movsx(rax, cl).unwrap();
+        a.movsx(r8d, dx).unwrap();
+        a.movsxd(r9, r8d).unwrap();
+        a.add(rax, r9).unwrap();
         a.ret().unwrap();
 
         let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
         let m = Machine::new(bytecode.as_ptr());
-        let f: extern "C" fn(u32, &mut u32) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
-        assert_eq!(f(0xFFFFFFFFu32, &mut higher_bits), 0xfffffffd);
-        assert_eq!(higher_bits, 0x2);
+        let f: extern "C" fn(u64, u64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0xff80, 0xffff_8000), -128 - 0x8000);
+        assert_eq!(f(0x7f, 0x7fff), 0x7f + 0x7fff);
 
+        // cbw, cwde, cdqe chain
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, rcx).unwrap();
+        a.cbw().unwrap();
+        a.cwde().unwrap();
+        a.cdqe().unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0x1234_5678_9abc_def0), -16);
+        assert_eq!(f(0x1234_5678_9abc_de70), 0x70);
+
+        // cdq leaves the upper half of rax alone
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, rcx).unwrap();
+        a.cdq().unwrap();
+        a.add(rax, rdx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0x1_8000_0000), 0x1_8000_0000 + 0xffff_ffff);
+        assert_eq!(f(0x1_7fff_ffff), 0x1_7fff_ffff);
+
+        // cqo; idiv
         let mut a = CodeAssembler::new(64).unwrap();
-        a.imul_2(rcx, rdx).unwrap();
         a.mov(rax, rcx).unwrap();
+        a.mov(r8, rdx).unwrap();
+        a.cqo().unwrap();
+        a.idiv(r8).unwrap();
         a.ret().unwrap();
 
         let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
         let m = Machine::new(bytecode.as_ptr());
         let f: extern "C" fn(i64, i64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
-        assert_eq!(f(-5, 2), -10);
+        assert_eq!(f(-100, 7), -14);
+        assert_eq!(f(100, -7), -14);
+        assert_eq!(f(100, 7), 14);
+    }
 
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_atomics() {
+        use iced_x86::code_asm::*;
+        // xadd returns the old value, xchg reg,reg is lifted
         let mut a = CodeAssembler::new(64).unwrap();
-        a.imul_3(rax, rcx, 4i32).unwrap();
+        a.lock().xadd(dword_ptr(rcx), edx).unwrap();
+        a.xchg(eax, edx).unwrap();
         a.ret().unwrap();
 
         let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
         let m = Machine::new(bytecode.as_ptr());
-        let f: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
-        assert_eq!(f(-5), -20);
+        let f: extern "C" fn(*mut u32, u32) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
+        let mut value = 40;
+        assert_eq!(f(&mut value, 2), 40);
+        assert_eq!(value, 42);
+
+        // cmpxchg only loads the accumulator on failure
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, rdx).unwrap();
+        a.lock().cmpxchg(qword_ptr(rcx), r8).unwrap();
+        a.sete(dl).unwrap();
+        a.movzx(edx, dl).unwrap();
+        a.add(rax, rdx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*mut u64, u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        let mut value = 5;
+        assert_eq!(f(&mut value, 5, 9), 6);
+        assert_eq!(value, 9);
+        assert_eq!(f(&mut value, 5, 7), 9);
+        assert_eq!(value, 9);
+
+        // xchg with memory and lock or/dec
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.xchg(qword_ptr(rcx), rdx).unwrap();
+        a.lock().or(qword_ptr(rcx), 0x100).unwrap();
+        a.lock().dec(qword_ptr(rcx)).unwrap();
+        a.mov(rax, rdx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*mut u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        let mut value = 3;
+        assert_eq!(f(&mut value, 1), 3);
+        assert_eq!(value, 0x100);
+
+        // locked ops without a handler run natively through vmexec
+        #[repr(C, align(16))]
+        struct Values([u64; 6]);
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.push(rbx).unwrap();
+        a.mov(r8, rcx).unwrap();
+        a.stc().unwrap();
+        a.lock().adc(qword_ptr(r8), 2).unwrap();
+        a.lock().sbb(qword_ptr(r8 + 8), 1).unwrap();
+        a.lock().neg(qword_ptr(r8 + 16)).unwrap();
+        a.xor(eax, eax).unwrap();
+        a.xor(edx, edx).unwrap();
+        a.mov(ebx, 7).unwrap();
+        a.mov(ecx, 8).unwrap();
+        a.lock().cmpxchg8b(qword_ptr(r8 + 24)).unwrap();
+        a.lock().cmpxchg16b(xmmword_ptr(r8 + 32)).unwrap();
+        a.sete(al).unwrap();
+        a.pop(rbx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*mut Values) -> u8 = unsafe { std::mem::transmute(m.vmenter) };
+        let mut values = Values([40, 10, 5, 0, 0, 0]);
+        assert_eq!(f(&mut values), 1);
+        assert_eq!(values.0, [43, 9, (-5i64) as u64, 0x8_0000_0007, 7, 8]);
+
+        // concurrent increments don't get lost
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut lp = a.create_label();
+        a.set_label(&mut lp).unwrap();
+        a.lock().inc(dword_ptr(rcx)).unwrap();
+        a.dec(edx).unwrap();
+        a.jnz(lp).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let counter = std::sync::atomic::AtomicU32::new(0);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let m = Machine::new(bytecode.as_ptr());
+                    let f: extern "C" fn(*const std::sync::atomic::AtomicU32, u32) = unsafe { std::mem::transmute(m.vmenter) };
+                    f(&counter, 10000);
+                });
+            }
+        });
+        assert_eq!(counter.into_inner(), 40000);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_jump_table() {
+        use guardian::virtualizer::Virtualizer;
+        use iced_x86::BlockEncoderOptions;
+        use iced_x86::code_asm::*;
+
+        let assemble = |entries: &[u32]| {
+            let mut a = CodeAssembler::new(64).unwrap();
+            let mut default = a.create_label();
+            let mut table = a.create_label();
+            let mut cases = [a.create_label(), a.create_label(), a.create_label()];
+            a.cmp(ecx, 3).unwrap();
+            a.ja(default).unwrap();
+            a.lea(rdx, ptr(table)).unwrap();
+            a.movsxd(rax, dword_ptr(rdx + rcx * 4)).unwrap();
+            a.add(rax, rdx).unwrap();
+            a.jmp(rax).unwrap();
+            for (i, case) in cases.iter_mut().enumerate() {
+                a.set_label(case).unwrap();
+                a.mov(eax, 10 + i as u32).unwrap();
+                a.ret().unwrap();
+            }
+            a.set_label(&mut default).unwrap();
+            a.mov(eax, u32::MAX).unwrap();
+            a.ret().unwrap();
+            a.set_label(&mut table).unwrap();
+            a.dd(entries).unwrap();
+
+            let result = a.assemble_options(0, BlockEncoderOptions::RETURN_NEW_INSTRUCTION_OFFSETS).unwrap();
+            let table = result.label_ip(&table).unwrap();
+            let cases = cases.map(|case| result.label_ip(&case).unwrap().wrapping_sub(table) as u32);
+            (result.inner.code_buffer, cases)
+        };
+
+        let (_, cases) = assemble(&[0; 4]);
+        let (code, _) = assemble(&[cases[0], cases[1], cases[2], cases[2]]);
+
+        // the table is read at runtime, so virtualize at the address of the code
+        let bytecode = Virtualizer::new().virtualize_with_ip(code.as_ptr() as u64, &code).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0), 10);
+        assert_eq!(f(1), 11);
+        assert_eq!(f(2), 12);
+        assert_eq!(f(3), 12);
+        assert_eq!(f(4), u32::MAX);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_indirect_jmp_exit() {
+        use iced_x86::code_asm::*;
+        extern "C" fn native(a: u64, _: u64) -> u64 { a * 2 }
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.add(rcx, 1).unwrap();
+        a.jmp(rdx).unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(20, native as *const () as u64), 42);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_vm_call() {
+        use guardian::virtualizer::Virtualizer;
+        use iced_x86::code_asm::*;
+
+        // callee doubles rcx and adds the tag bit of the return address on the real stack
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, qword_ptr(rsp)).unwrap();
+        a.shr(rax, 63).unwrap();
+        a.lea(rax, qword_ptr(rax + rcx * 2)).unwrap();
+        a.ret().unwrap();
+        let callee = a.assemble(0x1000).unwrap();
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.push(rbx).unwrap();
+        a.mov(rbx, rcx).unwrap();
+        a.call(0x1000).unwrap();
+        a.mov(rcx, rax).unwrap();
+        a.call(0x1000).unwrap();
+        a.add(rax, rbx).unwrap();
+        a.pop(rbx).unwrap();
+        a.ret().unwrap();
+        let caller = a.assemble(0x2000).unwrap();
+
+        let (bytecode, entries) = Virtualizer::new()
+            .virtualize_routines(&[(0x1000, &callee), (0x2000, &caller)])
+            .unwrap();
+
+        let m = Machine::new(unsafe { bytecode.as_ptr().add(entries[1]) });
+        let f: extern "C" fn(u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(5), 28);
+        assert_eq!(f(0), 3);
+
+        // the callee still returns natively
+        let m = Machine::new(unsafe { bytecode.as_ptr().add(entries[0]) });
+        let f: extern "C" fn(u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(21), 42);
+    }
+
+    #[cfg(target_env = "msvc")]
+    extern "C" fn tail_target(x: u64) -> u64 {
+        x * 2
+    }
+
+    // increments the argument and tail jumps to tail_target
+    #[cfg(target_env = "msvc")]
+    std::arch::global_asm!(
+        ".globl guardian_tail_callee",
+        "guardian_tail_callee:",
+        "lea rcx, [rcx + 1]",
+        "jmp {target}",
+        target = sym tail_target,
+    );
+
+    #[cfg(target_env = "msvc")]
+    extern "C" {
+        fn guardian_tail_callee();
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_vm_call_tail_jump() {
+        use guardian::virtualizer::Virtualizer;
+        use iced_x86::code_asm::*;
+
+        // the callee leaves the vm through its tail jump, a vm call would return natively to the tagged
+        // return address so it's called natively
+        let callee_ip = guardian_tail_callee as *const () as u64;
+        let callee = unsafe { std::slice::from_raw_parts(callee_ip as *const u8, 9) };
+        // native calls are relative to the image base in the peb
+        let image_base: u64;
+        unsafe { std::arch::asm!("mov {0}, gs:[0x60]", "mov {0}, [{0} + 0x10]", out(reg) image_base); }
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.sub(rsp, 0x28).unwrap();
+        a.call(callee_ip).unwrap();
+        a.add(rsp, 0x28).unwrap();
+        a.add(rax, 1).unwrap();
+        a.ret().unwrap();
+        let caller = a.assemble(callee_ip + 0x1000).unwrap();
+
+        let (bytecode, entries) = Virtualizer::new()
+            .with_image_base(image_base)
+            .virtualize_routines(&[(callee_ip, callee), (callee_ip + 0x1000, &caller)])
+            .unwrap();
+        let m = Machine::new(unsafe { bytecode.as_ptr().add(entries[1]) });
+        let f: extern "C" fn(u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(20), 43);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_cold_chunk() {
+        use guardian::virtualizer::Virtualizer;
+        use iced_x86::code_asm::*;
+
+        // the error path lives out of line like code the compiler moved to a cold section
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.test(rcx, rcx).unwrap();
+        a.je(0x3000).unwrap();
+        a.lea(rax, qword_ptr(rcx + 1)).unwrap();
+        a.ret().unwrap();
+        let hot = a.assemble(0x1000).unwrap();
+        let ret = 0x1000 + hot.len() as u64 - 1;
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, -1i64).unwrap();
+        a.cmp(rdx, 0).unwrap();
+        a.jne(ret).unwrap();
+        a.mov(rax, rdx).unwrap();
+        a.jmp(ret).unwrap();
+        let cold = a.assemble(0x3000).unwrap();
+
+        let bytecode = Virtualizer::new()
+            .virtualize_chunks(&[(0x1000, &hot), (0x3000, &cold)])
+            .unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(41, 0), 42);
+        assert_eq!(f(0, 7), u64::MAX);
+        assert_eq!(f(0, 0), 0);
+
+        // the vm starts at the entry even when the cold chunk lies below it
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.test(rcx, rcx).unwrap();
+        a.je(0x500).unwrap();
+        a.lea(rax, qword_ptr(rcx + 1)).unwrap();
+        a.ret().unwrap();
+        let hot = a.assemble(0x1000).unwrap();
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, -1i64).unwrap();
+        a.cmp(rdx, 0).unwrap();
+        a.jne(ret).unwrap();
+        a.mov(rax, rdx).unwrap();
+        a.jmp(ret).unwrap();
+        let cold = a.assemble(0x500).unwrap();
+
+        let bytecode = Virtualizer::new()
+            .virtualize_chunks(&[(0x1000, &hot), (0x500, &cold)])
+            .unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(41, 0), 42);
+        assert_eq!(f(0, 7), u64::MAX);
+        assert_eq!(f(0, 0), 0);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_control_flow_recovery() {
+        use guardian::virtualizer::Virtualizer;
+        use guardian::virtualizer::cfg::ControlFlowGraph;
+        use iced_x86::code_asm::*;
+
+        // data jumped over and int3 padding after the ret are never lifted
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut code = a.create_label();
+        a.mov(rax, rcx).unwrap();
+        a.jmp(code).unwrap();
+        a.db(&[0xff, 0xff, 0x0f, 0x0b, 0x62]).unwrap();
+        a.set_label(&mut code).unwrap();
+        a.add(rax, 1).unwrap();
+        a.ret().unwrap();
+        a.db(&[0xcc; 6]).unwrap();
+        let program = a.assemble(0x1000).unwrap();
+
+        let cfg = ControlFlowGraph::recover(&[(0x1000, &program)], |_, _| None).unwrap();
+        assert_eq!(cfg.blocks.len(), 2);
+        assert_eq!(cfg.data, vec![0x1005..0x100a]);
+
+        let bytecode = Virtualizer::new().virtualize_with_ip(0x1000, &program).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(41), 42);
+
+        // conditional and unconditional tail jumps continue natively in the other function
+        extern "C" fn double(x: u64) -> u64 {
+            x * 2
+        }
+        extern "C" fn negate(x: u64) -> u64 {
+            x.wrapping_neg()
+        }
+        let ip = double as *const () as u64 + 0x1000;
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.add(rcx, 1).unwrap();
+        a.cmp(rdx, 0).unwrap();
+        a.je(double as *const () as u64).unwrap();
+        a.jmp(negate as *const () as u64).unwrap();
+        let program = a.assemble(ip).unwrap();
+
+        let cfg = ControlFlowGraph::recover(&[(ip, &program)], |_, _| None).unwrap();
+        assert_eq!(cfg.tail_jumps.iter().map(|(_, target)| *target).collect::<Vec<_>>(),
+            vec![double as *const () as u64, negate as *const () as u64]);
+
+        let bytecode = Virtualizer::new().virtualize_with_ip(ip, &program).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(20, 0), 42);
+        assert_eq!(f(20, 1), -21i64 as u64);
+
+        // code that isn't a closed region is reported instead of lifted
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.test(rcx, rcx).unwrap();
+        a.jne(0x1000 + 0x20).unwrap();
+        a.mov(rax, rcx).unwrap();
+        let error = virtualize(&a.assemble(0).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("runs past the end"), "{error}");
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, 0x1234_5678_9abc_def0u64).unwrap();
+        a.jmp(2).unwrap();
+        let error = virtualize(&a.assemble(0).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("inside of the instruction at 0x0"), "{error}");
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_ret_imm_leave_enter() {
+        use guardian::virtualizer::Virtualizer;
+        use iced_x86::code_asm::*;
+
+        // ret 8 releases the pushed argument, rbx holds the stack imbalance
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, qword_ptr(rsp + 8)).unwrap();
+        a.add(rax, 1).unwrap();
+        a.ret_1(8).unwrap();
+        let callee = a.assemble(0x1000).unwrap();
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.push(rbx).unwrap();
+        a.mov(rbx, rsp).unwrap();
+        a.push(rcx).unwrap();
+        a.call(0x1000).unwrap();
+        a.sub(rbx, rsp).unwrap();
+        a.add(rax, rbx).unwrap();
+        a.pop(rbx).unwrap();
+        a.ret().unwrap();
+        let caller = a.assemble(0x2000).unwrap();
+
+        let (bytecode, entries) = Virtualizer::new()
+            .virtualize_routines(&[(0x1000, &callee), (0x2000, &caller)])
+            .unwrap();
+        let m = Machine::new(unsafe { bytecode.as_ptr().add(entries[1]) });
+        let f: extern "C" fn(u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(41), 42);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.enter(0x10u32, 0u32).unwrap();
+        a.mov(qword_ptr(rbp - 8), rcx).unwrap();
+        a.mov(rax, rsp).unwrap();
+        a.sub(rax, rbp).unwrap();
+        a.add(rax, qword_ptr(rbp - 8)).unwrap();
+        a.leave().unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(100), 84);
+
+        // nested enter copies the enclosing frame pointer
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.push(rbp).unwrap();
+        a.mov(rbp, rdx).unwrap();
+        a.enter(0u32, 2u32).unwrap();
+        a.mov(rax, qword_ptr(rsp + 8)).unwrap();
+        a.mov(rcx, qword_ptr(rsp)).unwrap();
+        a.sub(rcx, rbp).unwrap();
+        a.add(rax, rcx).unwrap();
+        a.leave().unwrap();
+        a.sub(rbp, rdx).unwrap();
+        a.add(rax, rbp).unwrap();
+        a.pop(rbp).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, *const u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        let frames = [0x55u64, 0];
+        assert_eq!(f(0, unsafe { frames.as_ptr().add(1) }), 0x55);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_bit_manipulation() {
+        use iced_x86::code_asm::*;
+        // register offsets address a bit string beyond the memory operand in both directions
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.bts(qword_ptr(rcx), rdx).unwrap();
+        a.setb(al).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*mut u64, i64) -> bool = unsafe { std::mem::transmute(m.vmenter) };
+        let mut bits = [0u64; 4];
+        assert!(!f(&mut bits[1], 130));
+        assert!(f(&mut bits[1], 130));
+        assert!(!f(&mut bits[1], -3));
+        assert_eq!(bits, [1 << 61, 0, 0, 1 << 2]);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.lock().btr(dword_ptr(rcx), edx).unwrap();
+        a.setb(al).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*mut u32, i32) -> bool = unsafe { std::mem::transmute(m.vmenter) };
+        let mut bits = [u32::MAX; 2];
+        assert!(f(&mut bits[0], 33));
+        assert!(!f(&mut bits[0], 33));
+        assert_eq!(bits, [u32::MAX, !2]);
+
+        // immediate offsets wrap around the operand size
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.btc(word_ptr(rcx), 17).unwrap();
+        a.bt(edx, 33).unwrap();
+        a.setb(al).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*mut u16, u32) -> bool = unsafe { std::mem::transmute(m.vmenter) };
+        let mut value = 0xff00u16;
+        assert!(f(&mut value, 2));
+        assert!(!f(&mut value, 1));
+        assert_eq!(value, 0xff00);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.btr(rcx, rdx).unwrap();
+        a.mov(rax, rcx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(u64::MAX, 127), u64::MAX >> 1);
+
+        // bsf and bsr keep the destination for a zero source
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, rdx).unwrap();
+        a.bsf(rax, rcx).unwrap();
+        a.bsr(rdx, rcx).unwrap();
+        a.shl(rax, 8).unwrap();
+        a.or(rax, rdx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0x50, 0x11), 0x406);
+        assert_eq!(f(0, 0x11), 0x1111);
+
+        // tzcnt and lzcnt return the operand size for a zero source and set cf
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.tzcnt(eax, ecx).unwrap();
+        a.setb(dl).unwrap();
+        a.lzcnt(r8w, cx).unwrap();
+        a.setb(r9b).unwrap();
+        a.shl(rax, 8).unwrap();
+        a.or(rax, r8).unwrap();
+        a.shl(rax, 1).unwrap();
+        a.or(al, dl).unwrap();
+        a.shl(rax, 1).unwrap();
+        a.or(al, r9b).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u32, u64, u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0x10_0008, 0, 0, 0), (3 << 8 | 12) << 2);
+        assert_eq!(f(0x10_0000, 0, 0, 0), (20 << 8 | 16) << 2 | 1);
+        assert_eq!(f(0, 0, 0, 0), (32 << 8 | 16) << 2 | 0b11);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.popcnt(rax, qword_ptr(rcx)).unwrap();
+        a.sete(dl).unwrap();
+        a.add(al, dl).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(&u64, u64) -> u8 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(&0xff00_0000_0000_00f1, 0), 13);
+        assert_eq!(f(&0, 0), 1);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.bswap(ecx).unwrap();
+        a.bswap(rdx).unwrap();
+        a.mov(rax, rcx).unwrap();
+        a.xor(rax, rdx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0xffff_ffff_1234_5678, 0), 0x7856_3412);
+        assert_eq!(f(0, 0x0102_0304_0506_0708), 0x0807_0605_0403_0201);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_string_ops() {
+        use iced_x86::code_asm::*;
+        // rsi and rdi are nonvolatile
+        let string = |body: fn(&mut CodeAssembler)| {
+            let mut a = CodeAssembler::new(64).unwrap();
+            a.push(rsi).unwrap();
+            a.push(rdi).unwrap();
+            body(&mut a);
+            a.pop(rdi).unwrap();
+            a.pop(rsi).unwrap();
+            a.ret().unwrap();
+            virtualize(&a.assemble(0).unwrap()).unwrap()
+        };
+
+        // memcpy
+        let bytecode = string(|a| {
+            a.mov(rdi, rcx).unwrap();
+            a.mov(rsi, rdx).unwrap();
+            a.mov(rcx, r8).unwrap();
+            a.rep().movsb().unwrap();
+            a.mov(rax, rdi).unwrap();
+        });
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*mut u8, *const u8, usize) -> *mut u8 = unsafe { std::mem::transmute(m.vmenter) };
+        let mut dst = [0u8; 16];
+        assert_eq!(f(dst.as_mut_ptr(), b"hello, world!".as_ptr(), 13), dst[13..].as_mut_ptr());
+        assert_eq!(&dst[..14], b"hello, world!\0");
+        assert_eq!(f(dst.as_mut_ptr(), b"nothing".as_ptr(), 0), dst.as_mut_ptr());
+        assert_eq!(&dst[..7], b"hello, ");
+
+        // memset
+        let bytecode = string(|a| {
+            a.mov(rdi, rcx).unwrap();
+            a.mov(rax, rdx).unwrap();
+            a.mov(rcx, r8).unwrap();
+            a.rep().stosq().unwrap();
+        });
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*mut u64, u64, usize) = unsafe { std::mem::transmute(m.vmenter) };
+        let mut dst = [0u64; 5];
+        f(dst.as_mut_ptr(), u64::MAX, 3);
+        assert_eq!(dst, [u64::MAX, u64::MAX, u64::MAX, 0, 0]);
+
+        // strlen
+        let bytecode = string(|a| {
+            a.xor(eax, eax).unwrap();
+            a.mov(rdi, rcx).unwrap();
+            a.mov(rcx, -1i64).unwrap();
+            a.repne().scasb().unwrap();
+            a.not(rcx).unwrap();
+            a.dec(rcx).unwrap();
+            a.mov(rax, rcx).unwrap();
+        });
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*const u8) -> usize = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(b"hello, world!\0".as_ptr()), 13);
+        assert_eq!(f(b"\0".as_ptr()), 0);
+
+        // memcmp returns the remaining count and whether the last compared bytes differ, an empty
+        // compare keeps the flags of the cmp before
+        let bytecode = string(|a| {
+            a.mov(rsi, rcx).unwrap();
+            a.mov(rdi, rdx).unwrap();
+            a.mov(rcx, r8).unwrap();
+            a.cmp(rcx, r9).unwrap();
+            a.repe().cmpsb().unwrap();
+            a.setne(dl).unwrap();
+            a.shl(rcx, 8).unwrap();
+            a.mov(cl, dl).unwrap();
+            a.mov(rax, rcx).unwrap();
+        });
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*const u8, *const u8, usize, usize) -> usize = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(b"abcdef".as_ptr(), b"abcxef".as_ptr(), 6, 0), 2 << 8 | 1);
+        assert_eq!(f(b"abcdef".as_ptr(), b"abcdef".as_ptr(), 6, 0), 0);
+        assert_eq!(f(b"a".as_ptr(), b"b".as_ptr(), 0, 1), 1);
+        assert_eq!(f(b"a".as_ptr(), b"b".as_ptr(), 0, 0), 0);
+
+        // memmove backwards with df set
+        let bytecode = string(|a| {
+            a.lea(rsi, qword_ptr(rcx + r8 - 1)).unwrap();
+            a.lea(rdi, qword_ptr(rdx + r8 - 1)).unwrap();
+            a.mov(rcx, r8).unwrap();
+            a.std().unwrap();
+            a.rep().movsb().unwrap();
+            a.cld().unwrap();
+        });
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*const u8, *mut u8, usize) = unsafe { std::mem::transmute(m.vmenter) };
+        let mut buffer = *b"abcdef__";
+        f(buffer.as_ptr(), buffer[2..].as_mut_ptr(), 6);
+        assert_eq!(&buffer, b"ababcdef");
+
+        // lods zero extends dword loads and keeps the upper bits for smaller ones
+        let bytecode = string(|a| {
+            a.mov(rsi, rcx).unwrap();
+            a.mov(rax, rdx).unwrap();
+            a.lodsw().unwrap();
+            a.lodsb().unwrap();
+            a.mov(rcx, rax).unwrap();
+            a.lodsd().unwrap();
+            a.add(rax, rcx).unwrap();
+        });
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(*const u8, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f([0x11, 0x22, 0x33, 0x01, 0, 0, 0].as_ptr(), 0xffff_ffff_ffff_ffff), 0xffff_ffff_ffff_2233 + 1);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_div() {
+        use iced_x86::code_asm::*;
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut remainder = 0;
+        a.mov(eax, 10).unwrap();
+        a.mov(r8, 8i64).unwrap();
+        a.xor(edx, edx).unwrap();
+        a.div(r8).unwrap(); // mov second argument to rcx (divisor)
+        a.mov(dword_ptr(rcx), edx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(&mut u32) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(&mut remainder), 1);
+        assert_eq!(remainder, 2);
+
+        // idiv
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut remainder = 0;
+        a.mov(eax, 4294967278u32).unwrap();
+        a.mov(r8d, 6i32).unwrap();
+        a.cdq().unwrap();
+        a.idiv(r8d).unwrap(); // mov second argument to rcx (divisor)
+        a.mov(dword_ptr(rcx), edx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(&mut i32) -> i32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(&mut remainder), -3);
+        assert_eq!(remainder, 0);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_shifts() {
+        use iced_x86::code_asm::*;
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, rcx).unwrap();
+        a.shl(rax, 4).unwrap();
+        a.mov(cl, 2).unwrap();
+        a.shr(eax, cl).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        // 32 bit shift zero extends into rax
+        assert_eq!(f(0x0F00_0000_0F00_0001), 0x3C00_0004);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, rcx).unwrap();
+        a.sar(rax, 4).unwrap();
+        a.sar(ax, 40).unwrap(); // count is masked to 8
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(-0x100), -1);
+        assert_eq!(f(0x7F00), 7);
+
+        // cf is the last bit shifted out
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut lbl = a.create_label();
+        a.xor(eax, eax).unwrap();
+        a.shr(cl, 1).unwrap();
+        a.jae(lbl).unwrap();
+        a.mov(eax, 1).unwrap();
+        a.set_label(&mut lbl).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u8) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(3), 1);
+        assert_eq!(f(2), 0);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_rotates() {
+        use iced_x86::code_asm::*;
+        // carry in comes from bit 0 of r9, cf and of are written to flags
+        let rotate = |op: fn(&mut CodeAssembler)| {
+            let bytecode = virtualize_body(|a| {
+                a.mov(rax, rcx).unwrap();
+                a.mov(ecx, edx).unwrap();
+                a.bt(r9, 0).unwrap();
+                op(a);
+                a.setb(byte_ptr(r8)).unwrap();
+                a.seto(byte_ptr(r8 + 1)).unwrap();
+            });
+            move |value: u64, count: u8, carry: bool| enter(&bytecode, |f: extern "C" fn(u64, u8, &mut [bool; 2], bool) -> u64| {
+                let mut flags = [false; 2];
+                let result = f(value, count, &mut flags, carry);
+                (result, flags)
+            })
+        };
+
+        // of is undefined for counts other than 1
+        let cf = |(result, flags): (u64, [bool; 2])| (result, flags[0]);
+
+        let rol = rotate(|a| a.rol(rax, cl).unwrap());
+        assert_eq!(cf(rol(0x8000_0000_0000_0001, 4, false)), (0x18, false));
+        assert_eq!(rol(0x8000_0000_0000_0001, 1, false), (0x3, [true, true]));
+        // a masked count of 0 leaves the flags alone
+        assert_eq!(rol(0x8000_0000_0000_0001, 64, true), (0x8000_0000_0000_0001, [true, false]));
+
+        let ror = rotate(|a| a.ror(ax, cl).unwrap());
+        assert_eq!(ror(0x1234_0001, 1, false), (0x1234_8000, [true, true]));
+        assert_eq!(cf(ror(0x1234_0003, 17, false)), (0x1234_8001, true));
+
+        let rcl = rotate(|a| a.rcl(al, cl).unwrap());
+        assert_eq!(rcl(0x80, 1, true), (0x01, [true, true]));
+        assert_eq!(cf(rcl(0x80, 9, false)), (0x80, false));
+        assert_eq!(cf(rcl(0x40, 2, false)), (0x00, true));
+
+        let rcr = rotate(|a| a.rcr(eax, cl).unwrap());
+        assert_eq!(rcr(0xffff_ffff_0000_0001, 1, true), (0x8000_0000, [true, true]));
+        assert_eq!(cf(rcr(0x2, 2, true)), (0x4000_0000, true));
+
+        let rcl = rotate(|a| a.rcl(rax, 1).unwrap());
+        assert_eq!(rcl(0x8000_0000_0000_0000, 0, false), (0, [true, true]));
+
+        let shld = rotate(|a| {
+            a.mov(edx, 0x9abc_def0u32).unwrap();
+            a.shld(eax, edx, cl).unwrap();
+        });
+        assert_eq!(cf(shld(0x1234_5678, 4, false)), (0x2345_6789, true));
+        assert_eq!(shld(0x4000_0000, 1, false), (0x8000_0001, [false, true]));
+        assert_eq!(shld(0x1234_5678, 32, true), (0x1234_5678, [true, false]));
+
+        let shrd = rotate(|a| {
+            a.mov(edx, 0xaa).unwrap();
+            a.shrd(rax, rdx, 8).unwrap();
+        });
+        assert_eq!(cf(shrd(0x1122_3344_5566_7788, 0, false)), (0xaa11_2233_4455_6677, true));
+
+        // high byte registers are accessed in place without touching the flags
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.mov(rax, rdx).unwrap();
+        a.cmp(cl, 5).unwrap();
+        a.mov(ah, cl).unwrap();
+        a.setb(al).unwrap();
+        a.add(ah, ch).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0x0203, 0xffff_0000), 0xffff_0501);
+        assert_eq!(f(0x0106, 0xffff_0000), 0xffff_0700);
+    }
+
+    #[test]
+    #[cfg(target_env = "msvc")]
+    fn virtualize_mul() {
+        use iced_x86::code_asm::*;
+        let mut a = CodeAssembler::new(64).unwrap();
+        let mut higher_bits = 0u32;
+        a.mov(eax, 3).unwrap();
+        a.mul(rcx).unwrap();
+        a.mov(r8, &mut higher_bits as *mut _ as u64).unwrap();
+        a.mov(dword_ptr(r8), edx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(u32, &mut u32) -> u32 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(0xFFFFFFFFu32, &mut higher_bits), 0xfffffffd);
+        // the 64 bit product is 0x2_fffffffd, rdx receives the upper half of the 128 bit result
+        assert_eq!(higher_bits, 0);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.imul_2(rcx, rdx).unwrap();
+        a.mov(rax, rcx).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(i64, i64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(-5, 2), -10);
+
+        let mut a = CodeAssembler::new(64).unwrap();
+        a.imul_3(rax, rcx, 4i32).unwrap();
+        a.ret().unwrap();
+
+        let bytecode = virtualize(&a.assemble(0).unwrap()).unwrap();
+        let m = Machine::new(bytecode.as_ptr());
+        let f: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(m.vmenter) };
+        assert_eq!(f(-5), -20);
+
+        // rax starts as the first argument and the second is in r9, returns rax, rdx and cf | of
+        let mul = |op: fn(&mut CodeAssembler)| {
+            let bytecode = virtualize_body(|a| {
+                a.mov(rax, rcx).unwrap();
+                a.mov(r9, rdx).unwrap();
+                a.mov(rdx, 0x7777_0000_0000u64).unwrap();
+                // set cf and of so clearing them is observable
+                a.mov(r10b, 0x80).unwrap();
+                a.add(r10b, r10b).unwrap();
+                op(a);
+                a.mov(qword_ptr(r8), rax).unwrap();
+                a.mov(qword_ptr(r8 + 8), rdx).unwrap();
+                a.pushfq().unwrap();
+                a.pop(rax).unwrap();
+                a.and(eax, 0x801).unwrap();
+            });
+            move |x: u64, y: u64| enter(&bytecode, |f: extern "C" fn(u64, u64, &mut [u64; 2]) -> u64| {
+                let mut out = [0; 2];
+                let flags = f(x, y, &mut out);
+                (out[0], out[1], flags)
+            })
+        };
+
+        let mul64 = mul(|a| a.mul(r9).unwrap());
+        assert_eq!(mul64(u64::MAX, u64::MAX), (1, 0xffff_ffff_ffff_fffe, 0x801));
+        assert_eq!(mul64(3, 5), (15, 0, 0));
+        let imul64 = mul(|a| a.imul(r9).unwrap());
+        assert_eq!(imul64(-5i64 as u64, 3), (-15i64 as u64, u64::MAX, 0));
+        assert_eq!(imul64(1 << 62, 4), (0, 1, 0x801));
+        assert_eq!(mul(|a| a.mul(r9d).unwrap())(0xffff_ffff, 0xffff_ffff), (1, 0xffff_fffe, 0x801));
+        assert_eq!(mul(|a| a.imul(r9w).unwrap())(0x1234_0000_0000_fffe, 3),
+            (0x1234_0000_0000_fffa, 0x7777_0000_ffff, 0));
+        assert_eq!(mul(|a| a.mul(r9b).unwrap())(0x10f0, 0x10), (0x0f00, 0x7777_0000_0000, 0x801));
+        assert_eq!(mul(|a| a.imul(r9b).unwrap())(0xff, 0x80), (0x80, 0x7777_0000_0000, 0x801));
+
+        // two and three operand forms truncate and set cf and of on signed overflow
+        let imul2 = mul(|a| a.imul_2(rax, r9).unwrap());
+        assert_eq!(imul2(i64::MAX as u64, 2), (-2i64 as u64, 0x7777_0000_0000, 0x801));
+        assert_eq!(imul2(-3i64 as u64, 7), (-21i64 as u64, 0x7777_0000_0000, 0));
+        assert_eq!(mul(|a| a.imul_2(ax, r9w).unwrap())(0x100, 0x100), (0, 0x7777_0000_0000, 0x801));
+        assert_eq!(mul(|a| a.imul_3(eax, r9d, 0x10000).unwrap())(-1i64 as u64, 0x10000), (0, 0x7777_0000_0000, 0x801));
+        assert_eq!(mul(|a| a.imul_3(eax, r9d, -2).unwrap())(0, 0x4000_0000), (0x8000_0000, 0x7777_0000_0000, 0));
     }
 
     #[test]
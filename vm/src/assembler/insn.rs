@@ -2,6 +2,7 @@
 
 mod add;
 mod call;
+mod cld;
 mod dec;
 mod jmp;
 mod jnz;
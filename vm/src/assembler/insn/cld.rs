@@ -0,0 +1,7 @@
+use super::super::prelude::Asm;
+
+impl Asm<'_> {
+    pub fn cld(&mut self) {
+        self.emit(&[0xFC]);
+    }
+}
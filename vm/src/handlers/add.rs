@@ -1,10 +1,20 @@
 use vm_proc::handler;
-use crate::{binary_op_save_flags, Machine, OpSize};
+use crate::{binary_op_carry_save_flags, binary_op_save_flags, step_save_flags, Machine, OpSize};
 use crate::macros::binary_op;
 
 #[handler]
 pub fn add(vm: &mut Machine, op_size: OpSize) {
-    binary_op_save_flags!(vm, op_size, wrapping_add, OF, SF, ZF, PF, CF_ADD);
+    binary_op_save_flags!(vm, op_size, wrapping_add, OF_ADD, SF, ZF, AF, PF, CF_ADD);
+}
+
+#[handler]
+pub fn adc(vm: &mut Machine, op_size: OpSize) {
+    binary_op_carry_save_flags!(vm, op_size, wrapping_add, OF_ADC, SF, ZF, AF, PF, CF_ADC);
+}
+
+#[handler]
+pub fn inc(vm: &mut Machine, op_size: OpSize) {
+    step_save_flags!(vm, op_size, wrapping_add, OF_ADD);
 }
 
 #[handler]
@@ -4,5 +4,5 @@ use crate::{binary_op_save_flags, Machine, OpSize};
 
 #[handler]
 pub fn and(vm: &mut Machine, op_size: OpSize) {
-    binary_op_save_flags!(vm, op_size, bitand, SF, ZF, PF);
+    binary_op_save_flags!(vm, op_size, bitand, SF, ZF, PF, CF_CLEAR, OF_CLEAR, AF_CLEAR);
 }
\ No newline at end of file
@@ -0,0 +1,113 @@
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, AtomicU8};
+use core::sync::atomic::Ordering::SeqCst;
+
+use vm_proc::handler;
+use x86::bits64::rflags::RFlags;
+
+use crate::{calculate_rflags, Machine, OpSize};
+use crate::shared::{AtomicOp, Register};
+
+/// Performs a locked read modify write on the address below the operands, the stack layout is
+/// `address, [expected], [src]` where cmpxchg takes the expected value and inc, dec and not take no src.
+/// Bts, btr and btc take the bit offset as src with the address already moved to the element holding the bit.
+/// `$keep` masks the bits of rax that survive a failed cmpxchg writing the accumulator.
+macro_rules! atomic_save_flags {
+    ($vm:ident, $op:ident, $atomic:ty, $bit:ty, $stack_bit:ty, $keep:expr) => {{
+        let src = match $op {
+            AtomicOp::Inc | AtomicOp::Dec | AtomicOp::Not => 0,
+            _ => $vm.stack_pop::<$stack_bit>() as $bit,
+        };
+        let expected = match $op {
+            AtomicOp::CmpXchg => $vm.stack_pop::<$stack_bit>() as $bit,
+            _ => 0,
+        };
+        let target = <$atomic>::from_ptr($vm.stack_pop::<*mut $bit>());
+
+        match $op {
+            AtomicOp::Xchg => $vm.stack_push(target.swap(src, SeqCst) as $stack_bit),
+            AtomicOp::Xadd => {
+                let old = target.fetch_add(src, SeqCst);
+                let result = old.wrapping_add(src);
+                calculate_rflags!($vm, old, src, result, OF_ADD, SF, ZF, AF, PF, CF_ADD);
+                $vm.stack_push(old as $stack_bit);
+            }
+            AtomicOp::CmpXchg => {
+                let (Ok(old) | Err(old)) = target.compare_exchange(expected, src, SeqCst, SeqCst);
+                let result = expected.wrapping_sub(old);
+                calculate_rflags!($vm, expected, old, result, OF_SUB, SF, ZF, AF, PF, CF_SUB);
+
+                if old != expected {
+                    let rax = &mut $vm.regs[Register::Rax as usize];
+                    *rax = (*rax & $keep) | old as u64;
+                }
+            }
+            AtomicOp::Add => {
+                let old = target.fetch_add(src, SeqCst);
+                let result = old.wrapping_add(src);
+                calculate_rflags!($vm, old, src, result, OF_ADD, SF, ZF, AF, PF, CF_ADD);
+            }
+            AtomicOp::Sub => {
+                let old = target.fetch_sub(src, SeqCst);
+                let result = old.wrapping_sub(src);
+                calculate_rflags!($vm, old, src, result, OF_SUB, SF, ZF, AF, PF, CF_SUB);
+            }
+            AtomicOp::And => {
+                let old = target.fetch_and(src, SeqCst);
+                let result = old & src;
+                calculate_rflags!($vm, old, src, result, SF, ZF, PF, CF_CLEAR, OF_CLEAR, AF_CLEAR);
+            }
+            AtomicOp::Or => {
+                let old = target.fetch_or(src, SeqCst);
+                let result = old | src;
+                calculate_rflags!($vm, old, src, result, SF, ZF, PF, CF_CLEAR, OF_CLEAR, AF_CLEAR);
+            }
+            AtomicOp::Xor => {
+                let old = target.fetch_xor(src, SeqCst);
+                let result = old ^ src;
+                calculate_rflags!($vm, old, src, result, SF, ZF, PF, CF_CLEAR, OF_CLEAR, AF_CLEAR);
+            }
+            // inc and dec leave cf alone
+            AtomicOp::Inc => {
+                let one: $bit = 1;
+                let old = target.fetch_add(one, SeqCst);
+                let result = old.wrapping_add(one);
+                calculate_rflags!($vm, old, one, result, OF_ADD, SF, ZF, AF, PF);
+            }
+            AtomicOp::Dec => {
+                let one: $bit = 1;
+                let old = target.fetch_sub(one, SeqCst);
+                let result = old.wrapping_sub(one);
+                calculate_rflags!($vm, old, one, result, OF_SUB, SF, ZF, AF, PF);
+            }
+            // not doesn't touch any flags
+            AtomicOp::Not => {
+                target.fetch_xor(<$bit>::MAX, SeqCst);
+            }
+            AtomicOp::Bts | AtomicOp::Btr | AtomicOp::Btc => {
+                let mask: $bit = 1 << (src as u32 % <$bit>::BITS);
+                let old = match $op {
+                    AtomicOp::Bts => target.fetch_or(mask, SeqCst),
+                    AtomicOp::Btr => target.fetch_and(!mask, SeqCst),
+                    _ => target.fetch_xor(mask, SeqCst),
+                };
+                let mut rflags = RFlags::from_bits_truncate($vm.rflags);
+                rflags.set(RFlags::FLAGS_CF, old & mask != 0);
+                $vm.rflags = rflags.bits();
+            }
+        }
+    }}
+}
+
+#[handler]
+pub unsafe fn atomic(vm: &mut Machine, op_size: OpSize) {
+    let op = AtomicOp::try_from(*vm.pc).unwrap();
+    vm.pc = vm.pc.add(1); // skip atomic op
+
+    match op_size {
+        OpSize::Qword => atomic_save_flags!(vm, op, AtomicU64, u64, u64, 0),
+        // 32 bit writes zero extend
+        OpSize::Dword => atomic_save_flags!(vm, op, AtomicU32, u32, u32, 0),
+        OpSize::Word => atomic_save_flags!(vm, op, AtomicU16, u16, u16, !0xffff),
+        OpSize::Byte => atomic_save_flags!(vm, op, AtomicU8, u8, u16, !0xff),
+    }
+}
@@ -0,0 +1,143 @@
+use x86::bits64::rflags::RFlags;
+use vm_proc::handler;
+use crate::{Machine, OpSize};
+
+fn set_flag(vm: &mut Machine, flag: RFlags, value: bool) {
+    let mut rflags = RFlags::from_bits_truncate(vm.rflags);
+    rflags.set(flag, value);
+    vm.rflags = rflags.bits();
+}
+
+/// Pops the bit offset and the value, the offset is taken modulo the operand size. Cf receives the selected
+/// bit and the value is modified by `$op` if given, bt has no byte form.
+macro_rules! bit_test {
+    ($vm:ident, $op_size:ident $(, |$value:ident, $mask:ident| $op:expr)?) => {
+        match $op_size {
+            OpSize::Qword => bit_test!($vm, u64, u64 $(, |$value, $mask| $op)?;),
+            OpSize::Dword => bit_test!($vm, u32, u32 $(, |$value, $mask| $op)?;),
+            OpSize::Word => bit_test!($vm, u16, u16 $(, |$value, $mask| $op)?;),
+            OpSize::Byte => unreachable!(),
+        }
+    };
+    ($vm:ident, $bit:ty, $stack_bit:ty $(, |$value:ident, $mask:ident| $op:expr)?;) => {{
+        let offset = $vm.stack_pop::<$stack_bit>() as $bit;
+        let value = $vm.stack_pop::<$stack_bit>() as $bit;
+        let mask: $bit = 1 << (offset as u32 % <$bit>::BITS);
+        set_flag($vm, RFlags::FLAGS_CF, value & mask != 0);
+        $(
+            let ($value, $mask) = (value, mask);
+            $vm.stack_push($op as $stack_bit);
+        )?
+    }};
+}
+
+/// Pops the source and pushes the result of `$op` with the source bound to `$src`, evaluates to whether the
+/// source and the result are zero. Bsf and bsr pop the destination too and keep it if the source is zero
+macro_rules! bit_count {
+    ($vm:ident, $op_size:ident, $($scan:ident)? |$src:ident| $op:expr) => {
+        match $op_size {
+            OpSize::Qword => bit_count!($vm, u64, u64, $($scan)? |$src| $op;),
+            OpSize::Dword => bit_count!($vm, u32, u32, $($scan)? |$src| $op;),
+            OpSize::Word => bit_count!($vm, u16, u16, $($scan)? |$src| $op;),
+            OpSize::Byte => unreachable!(),
+        }
+    };
+    ($vm:ident, $bit:ty, $stack_bit:ty, |$src:ident| $op:expr;) => {{
+        let $src = $vm.stack_pop::<$stack_bit>() as $bit;
+        let result = $op as $bit;
+        $vm.stack_push(result as $stack_bit);
+        ($src == 0, result == 0)
+    }};
+    ($vm:ident, $bit:ty, $stack_bit:ty, scan |$src:ident| $op:expr;) => {{
+        let $src = $vm.stack_pop::<$stack_bit>() as $bit;
+        let dst = $vm.stack_pop::<$stack_bit>() as $bit;
+        let result = if $src == 0 { dst } else { $op as $bit };
+        $vm.stack_push(result as $stack_bit);
+        set_flag($vm, RFlags::FLAGS_ZF, $src == 0);
+    }};
+}
+
+#[handler]
+pub unsafe fn bt(vm: &mut Machine, op_size: OpSize) {
+    bit_test!(vm, op_size)
+}
+
+#[handler]
+pub unsafe fn bts(vm: &mut Machine, op_size: OpSize) {
+    bit_test!(vm, op_size, |value, mask| value | mask)
+}
+
+#[handler]
+pub unsafe fn btr(vm: &mut Machine, op_size: OpSize) {
+    bit_test!(vm, op_size, |value, mask| value & !mask)
+}
+
+#[handler]
+pub unsafe fn btc(vm: &mut Machine, op_size: OpSize) {
+    bit_test!(vm, op_size, |value, mask| value ^ mask)
+}
+
+/// Moves the address of a bit string to the operand sized element holding the bit of the signed bit offset,
+/// used by the memory forms of bt with a register offset
+#[handler]
+pub unsafe fn bit_addr(vm: &mut Machine, op_size: OpSize) {
+    let offset = match op_size {
+        OpSize::Qword => vm.stack_pop::<u64>() as i64,
+        OpSize::Dword => vm.stack_pop::<u32>() as i32 as i64,
+        OpSize::Word => vm.stack_pop::<u16>() as i16 as i64,
+        OpSize::Byte => unreachable!(),
+    };
+    let size = op_size as u8 as i64;
+    let address = vm.stack_pop::<u64>();
+    vm.stack_push(address.wrapping_add((offset.div_euclid(size * 8) * size) as u64));
+}
+
+#[handler]
+pub unsafe fn bsf(vm: &mut Machine, op_size: OpSize) {
+    bit_count!(vm, op_size, scan |src| src.trailing_zeros())
+}
+
+#[handler]
+pub unsafe fn bsr(vm: &mut Machine, op_size: OpSize) {
+    bit_count!(vm, op_size, scan |src| (core::mem::size_of_val(&src) * 8) as u32 - 1 - src.leading_zeros())
+}
+
+// the result is the operand size for a zero source which sets cf
+#[handler]
+pub unsafe fn tzcnt(vm: &mut Machine, op_size: OpSize) {
+    let (src_zero, result_zero) = bit_count!(vm, op_size, |src| src.trailing_zeros());
+    set_flag(vm, RFlags::FLAGS_CF, src_zero);
+    set_flag(vm, RFlags::FLAGS_ZF, result_zero);
+}
+
+#[handler]
+pub unsafe fn lzcnt(vm: &mut Machine, op_size: OpSize) {
+    let (src_zero, result_zero) = bit_count!(vm, op_size, |src| src.leading_zeros());
+    set_flag(vm, RFlags::FLAGS_CF, src_zero);
+    set_flag(vm, RFlags::FLAGS_ZF, result_zero);
+}
+
+#[handler]
+pub unsafe fn popcnt(vm: &mut Machine, op_size: OpSize) {
+    let (src_zero, _) = bit_count!(vm, op_size, |src| src.count_ones());
+    let mut rflags = RFlags::from_bits_truncate(vm.rflags);
+    rflags.remove(RFlags::FLAGS_CF | RFlags::FLAGS_OF | RFlags::FLAGS_SF | RFlags::FLAGS_AF | RFlags::FLAGS_PF);
+    rflags.set(RFlags::FLAGS_ZF, src_zero);
+    vm.rflags = rflags.bits();
+}
+
+// bswap of a 16 bit operand is undefined
+#[handler]
+pub unsafe fn bswap(vm: &mut Machine, op_size: OpSize) {
+    match op_size {
+        OpSize::Qword => {
+            let value = vm.stack_pop::<u64>();
+            vm.stack_push(value.swap_bytes());
+        }
+        OpSize::Dword => {
+            let value = vm.stack_pop::<u32>();
+            vm.stack_push(value.swap_bytes());
+        }
+        OpSize::Word | OpSize::Byte => unreachable!(),
+    }
+}
@@ -0,0 +1,36 @@
+use core::mem::size_of;
+
+use vm_proc::handler;
+
+use crate::{Machine, OpSize};
+use crate::shared::{Register, VIRTUAL_RETURN};
+
+/// Calls the bytecode of another virtualized routine in the same machine, the tagged pc is pushed onto the
+/// real stack as return address
+#[handler]
+pub unsafe fn vm_call(vm: &mut Machine, _op_size: OpSize) {
+    let offset = vm.pc.cast::<i64>().read_unaligned();
+    let ret = vm.pc.add(size_of::<u64>());
+
+    let rsp = &mut vm.regs[Register::Rsp as usize];
+    *rsp -= size_of::<u64>() as u64;
+    (*rsp as *mut u64).write_unaligned(ret as u64 | VIRTUAL_RETURN);
+
+    #[cfg(not(feature = "threaded"))] {
+        vm.pc = (vm.pc.sub(2) as i64).wrapping_sub(offset) as _;
+    }
+    // same as jmp, - 8 lands on the handler of the first instruction
+    #[cfg(feature = "threaded")] {
+        vm.pc = ((vm.pc.sub(1) as i64).wrapping_sub(offset) - 8) as _;
+    }
+}
+
+/// Pops a return address pushed by vm_call and continues there
+#[handler]
+pub unsafe fn vm_ret(vm: &mut Machine, _op_size: OpSize) {
+    let rsp = &mut vm.regs[Register::Rsp as usize];
+    let ret = (*rsp as *const u64).read_unaligned();
+    *rsp += size_of::<u64>() as u64;
+
+    vm.pc = (ret & !VIRTUAL_RETURN) as _;
+}
@@ -7,22 +7,22 @@ pub unsafe fn cmp(vm: &mut Machine, op_size: OpSize) {
         OpSize::Qword => {
             let (op2, op1) = (vm.stack_pop::<u64>(), vm.stack_pop::<u64>());
             let result = op1.wrapping_sub(op2);
-            calculate_rflags!(vm, op1, op2, result, OF, SF, ZF, PF, CF_SUB);
+            calculate_rflags!(vm, op1, op2, result, OF_SUB, SF, ZF, AF, PF, CF_SUB);
         },
         OpSize::Dword => {
             let (op2, op1) = (vm.stack_pop::<u32>(), vm.stack_pop::<u32>());
             let result = op1.wrapping_sub(op2);
-            calculate_rflags!(vm, op1, op2, result, OF, SF, ZF, PF, CF_SUB);
+            calculate_rflags!(vm, op1, op2, result, OF_SUB, SF, ZF, AF, PF, CF_SUB);
         },
         OpSize::Word => {
             let (op2, op1) = (vm.stack_pop::<u16>(), vm.stack_pop::<u16>());
             let result = op1.wrapping_sub(op2);
-            calculate_rflags!(vm, op1, op2, result, OF, SF, ZF, PF, CF_SUB);
+            calculate_rflags!(vm, op1, op2, result, OF_SUB, SF, ZF, AF, PF, CF_SUB);
         },
         OpSize::Byte => {
             let (op2, op1) = (vm.stack_pop::<u16>() as u8, vm.stack_pop::<u16>() as u8);
             let result = op1.wrapping_sub(op2);
-            calculate_rflags!(vm, op1, op2, result, OF, SF, ZF, PF, CF_SUB);
+            calculate_rflags!(vm, op1, op2, result, OF_SUB, SF, ZF, AF, PF, CF_SUB);
         },
     }
 }
\ No newline at end of file
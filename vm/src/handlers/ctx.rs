@@ -1,3 +1,4 @@
+use core::arch::asm;
 use vm_proc::handler;
 use crate::Machine;
 use crate::shared::OpSize;
@@ -5,4 +6,22 @@ use crate::shared::OpSize;
 #[handler]
 pub unsafe fn vm_ctx(vm: &mut Machine, _op_size: OpSize) {
     vm.stack_push(vm as *const _ as u64)
-}
\ No newline at end of file
+}
+
+/// Pushes the gs base, which is the teb of the current thread. The teb stores its own address in
+/// NtTib.Self
+#[handler]
+pub unsafe fn gs_base(vm: &mut Machine, _op_size: OpSize) {
+    let base: u64;
+    asm!("mov {}, qword ptr gs:[0x30]", out(reg) base, options(nostack, readonly, preserves_flags));
+    vm.stack_push(base)
+}
+
+/// Pushes the fs base. Nothing in the thread stores its own fs base, so it's read with rdfsbase, which
+/// faults unless the os enables FSGSBASE for user mode
+#[handler]
+pub unsafe fn fs_base(vm: &mut Machine, _op_size: OpSize) {
+    let base: u64;
+    asm!("rdfsbase {}", out(reg) base, options(nomem, nostack, preserves_flags));
+    vm.stack_push(base)
+}
@@ -1,4 +1,4 @@
-use crate::{binary_op_sized, Machine, OpSize};
+use crate::{Machine, OpSize};
 
 macro_rules! div_save_flags {
     ($self:ident, $bit:ident, $save_bit:ident) => {{
@@ -36,8 +36,3 @@ pub fn idiv(vm: &mut Machine, op_size: OpSize) {
         OpSize::Byte => div_save_flags!(vm, i16, i16),
     }
 }
-
-#[handler]
-pub fn shr(vm: &mut Machine, op_size: OpSize) {
-    binary_op_sized!(vm, op_size, wrapping_div);
-}
@@ -134,6 +134,8 @@ fn reloc_instr(
     asm.pushfq();
     asm.pop(rax);
     asm.mov(MemOp::IndirectDisp(rcx, offset_of!(Machine, rflags) as i32), rax);
+    // a std executed here only sets df for the vm, the vm itself runs with df clear as the abi requires
+    asm.cld();
 
     asm.pop(rax);
     // save rsp after stack ptr is adjusted again
@@ -0,0 +1,25 @@
+use vm_proc::handler;
+use crate::{Machine, OpSize};
+
+/// Sign extends the value to twice its size, bytes are stored as words on the stack
+#[handler]
+pub unsafe fn sign_extend(vm: &mut Machine, op_size: OpSize) {
+    match op_size {
+        OpSize::Qword => {
+            let value = vm.stack_pop::<u64>() as i64;
+            vm.stack_push(value as i128 as u128);
+        }
+        OpSize::Dword => {
+            let value = vm.stack_pop::<u32>() as i32;
+            vm.stack_push(value as i64 as u64);
+        }
+        OpSize::Word => {
+            let value = vm.stack_pop::<u16>() as i16;
+            vm.stack_push(value as i32 as u32);
+        }
+        OpSize::Byte => {
+            let value = vm.stack_pop::<u16>() as u8 as i8;
+            vm.stack_push(value as i16 as u16);
+        }
+    }
+}
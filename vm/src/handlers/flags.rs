@@ -0,0 +1,19 @@
+use x86::bits64::rflags::RFlags;
+use vm_proc::handler;
+use crate::{Machine, OpSize};
+
+/// Merges the popped value into rflags. Qword is popfq which can only write the flags user mode
+/// is allowed to change, byte is sahf which loads sf, zf, af, pf and cf from ah
+#[handler]
+pub unsafe fn popf(vm: &mut Machine, op_size: OpSize) {
+    let (value, mask) = match op_size {
+        OpSize::Qword => (vm.stack_pop::<u64>(), RFlags::FLAGS_CF | RFlags::FLAGS_PF | RFlags::FLAGS_AF
+            | RFlags::FLAGS_ZF | RFlags::FLAGS_SF | RFlags::FLAGS_TF | RFlags::FLAGS_DF | RFlags::FLAGS_OF
+            | RFlags::FLAGS_NT | RFlags::FLAGS_AC | RFlags::FLAGS_ID),
+        OpSize::Byte => (vm.stack_pop::<u16>() as u64, RFlags::FLAGS_CF | RFlags::FLAGS_PF | RFlags::FLAGS_AF
+            | RFlags::FLAGS_ZF | RFlags::FLAGS_SF),
+        _ => unreachable!(),
+    };
+
+    vm.rflags = vm.rflags & !mask.bits() | value & mask.bits();
+}
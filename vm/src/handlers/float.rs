@@ -0,0 +1,126 @@
+use core::arch::asm;
+use x86::bits64::rflags::RFlags;
+use vm_proc::handler;
+use crate::{Machine, OpSize};
+use crate::shared::FloatOp;
+
+// the vm never touches mxcsr so the guest rounding mode and exception masks are live, executing the
+// real instruction honors them and updates the sticky exception flags exactly like native code would
+
+/// `$insn$suffix dst, src` on two scalars held in xmm registers
+macro_rules! sse_binary {
+    ($insn:literal, $suffix:literal, $dst:expr, $src:expr) => {{
+        let mut dst = $dst;
+        asm!(concat!($insn, $suffix, " {dst}, {src}"), dst = inout(xmm_reg) dst, src = in(xmm_reg) $src,
+            options(nomem, nostack));
+        dst
+    }};
+}
+
+/// Compares the scalars and returns zf, pf and cf, unordered operands set all of them
+macro_rules! sse_compare {
+    ($insn:literal, $suffix:literal, $dst:expr, $src:expr) => {{
+        let (zf, pf, cf): (u8, u8, u8);
+        asm!(concat!($insn, $suffix, " {dst}, {src}"), "setz {zf}", "setp {pf}", "setc {cf}",
+            dst = in(xmm_reg) $dst, src = in(xmm_reg) $src,
+            zf = out(reg_byte) zf, pf = out(reg_byte) pf, cf = out(reg_byte) cf,
+            options(nomem, nostack));
+        (zf != 0, pf != 0, cf != 0)
+    }};
+}
+
+/// Scalar single or double precision operations, `$float` is the type of the scalar and `$bits` its
+/// representation on the stack. Conversions to the other precision use `$other`
+macro_rules! float_op {
+    ($vm:ident, $op:ident, $float:ty, $bits:ty, $other:ty, $suffix:literal, $other_suffix:literal) => {{
+        match $op {
+            FloatOp::Add | FloatOp::Sub | FloatOp::Mul | FloatOp::Div | FloatOp::Min | FloatOp::Max => {
+                let src = <$float>::from_bits($vm.stack_pop::<$bits>());
+                let dst = <$float>::from_bits($vm.stack_pop::<$bits>());
+                let result = match $op {
+                    FloatOp::Add => sse_binary!("add", $suffix, dst, src),
+                    FloatOp::Sub => sse_binary!("sub", $suffix, dst, src),
+                    FloatOp::Mul => sse_binary!("mul", $suffix, dst, src),
+                    FloatOp::Div => sse_binary!("div", $suffix, dst, src),
+                    FloatOp::Min => sse_binary!("min", $suffix, dst, src),
+                    _ => sse_binary!("max", $suffix, dst, src),
+                };
+                $vm.stack_push(result.to_bits());
+            }
+            FloatOp::Sqrt => {
+                let src = <$float>::from_bits($vm.stack_pop::<$bits>());
+                $vm.stack_push(sse_binary!("sqrt", $suffix, src, src).to_bits());
+            }
+            FloatOp::UComi | FloatOp::Comi => {
+                let src = <$float>::from_bits($vm.stack_pop::<$bits>());
+                let dst = <$float>::from_bits($vm.stack_pop::<$bits>());
+                let (zf, pf, cf) = match $op {
+                    FloatOp::UComi => sse_compare!("ucomi", $suffix, dst, src),
+                    _ => sse_compare!("comi", $suffix, dst, src),
+                };
+                let mut rflags = RFlags::from_bits_truncate($vm.rflags);
+                rflags.remove(RFlags::FLAGS_OF | RFlags::FLAGS_SF | RFlags::FLAGS_AF);
+                rflags.set(RFlags::FLAGS_ZF, zf);
+                rflags.set(RFlags::FLAGS_PF, pf);
+                rflags.set(RFlags::FLAGS_CF, cf);
+                $vm.rflags = rflags.bits();
+            }
+            FloatOp::ToInt32 | FloatOp::TruncToInt32 => {
+                let src = <$float>::from_bits($vm.stack_pop::<$bits>());
+                let result: u32;
+                match $op {
+                    FloatOp::ToInt32 => asm!(concat!("cvt", $suffix, "2si {dst:e}, {src}"),
+                        dst = out(reg) result, src = in(xmm_reg) src, options(nomem, nostack)),
+                    _ => asm!(concat!("cvtt", $suffix, "2si {dst:e}, {src}"),
+                        dst = out(reg) result, src = in(xmm_reg) src, options(nomem, nostack)),
+                }
+                $vm.stack_push(result);
+            }
+            FloatOp::ToInt64 | FloatOp::TruncToInt64 => {
+                let src = <$float>::from_bits($vm.stack_pop::<$bits>());
+                let result: u64;
+                match $op {
+                    FloatOp::ToInt64 => asm!(concat!("cvt", $suffix, "2si {dst}, {src}"),
+                        dst = out(reg) result, src = in(xmm_reg) src, options(nomem, nostack)),
+                    _ => asm!(concat!("cvtt", $suffix, "2si {dst}, {src}"),
+                        dst = out(reg) result, src = in(xmm_reg) src, options(nomem, nostack)),
+                }
+                $vm.stack_push(result);
+            }
+            FloatOp::FromInt32 => {
+                let src = $vm.stack_pop::<u32>();
+                let result: $float;
+                asm!(concat!("cvtsi2", $suffix, " {dst}, {src:e}"),
+                    dst = out(xmm_reg) result, src = in(reg) src, options(nomem, nostack));
+                $vm.stack_push(result.to_bits());
+            }
+            FloatOp::FromInt64 => {
+                let src = $vm.stack_pop::<u64>();
+                let result: $float;
+                asm!(concat!("cvtsi2", $suffix, " {dst}, {src}"),
+                    dst = out(xmm_reg) result, src = in(reg) src, options(nomem, nostack));
+                $vm.stack_push(result.to_bits());
+            }
+            FloatOp::ToOther => {
+                let src = <$float>::from_bits($vm.stack_pop::<$bits>());
+                let result: $other;
+                asm!(concat!("cvt", $suffix, "2", $other_suffix, " {dst}, {src}"),
+                    dst = out(xmm_reg) result, src = in(xmm_reg) src, options(nomem, nostack));
+                $vm.stack_push(result.to_bits());
+            }
+        }
+    }}
+}
+
+/// The op size is the size of the scalar operand, dword for single and qword for double precision
+#[handler]
+pub unsafe fn float(vm: &mut Machine, op_size: OpSize) {
+    let op = FloatOp::try_from(*vm.pc).unwrap();
+    vm.pc = vm.pc.add(1); // skip float op
+
+    match op_size {
+        OpSize::Qword => float_op!(vm, op, f64, u64, f32, "sd", "ss"),
+        OpSize::Dword => float_op!(vm, op, f32, u32, f64, "ss", "sd"),
+        _ => unreachable!(),
+    }
+}
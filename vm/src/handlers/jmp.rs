@@ -3,12 +3,12 @@ use core::ops::BitXor;
 use x86::bits64::rflags::RFlags;
 use vm_proc::handler;
 use crate::Machine;
-use crate::shared::{JmpCond, OpSize};
+use crate::shared::{JmpCond, OpSize, Register};
 
-#[handler]
-pub unsafe fn jmp(vm: &mut Machine, _op_size: OpSize) {
+/// Evaluates a condition against the current rflags, shared by jmp and cond
+pub fn condition(vm: &Machine, cond: JmpCond) -> bool {
     let rflags = RFlags::from_bits_truncate(vm.rflags);
-    let do_jmp = match JmpCond::try_from(*vm.pc).unwrap() {
+    match cond {
         JmpCond::Jmp => true,
         JmpCond::Je => rflags.contains(RFlags::FLAGS_ZF),
         JmpCond::Jne => !rflags.contains(RFlags::FLAGS_ZF),
@@ -22,8 +22,27 @@ pub unsafe fn jmp(vm: &mut Machine, _op_size: OpSize) {
             || rflags.contains(RFlags::FLAGS_ZF),
         JmpCond::Jg => rflags.contains(RFlags::FLAGS_SF)
             == rflags.contains(RFlags::FLAGS_OF)
-            && !rflags.contains(RFlags::FLAGS_ZF)
-    };
+            && !rflags.contains(RFlags::FLAGS_ZF),
+        JmpCond::Jb => rflags.contains(RFlags::FLAGS_CF),
+        JmpCond::Jl => rflags.contains(RFlags::FLAGS_SF)
+            .bitxor(rflags.contains(RFlags::FLAGS_OF)),
+        JmpCond::Jge => rflags.contains(RFlags::FLAGS_SF)
+            == rflags.contains(RFlags::FLAGS_OF),
+        JmpCond::Js => rflags.contains(RFlags::FLAGS_SF),
+        JmpCond::Jns => !rflags.contains(RFlags::FLAGS_SF),
+        JmpCond::Jo => rflags.contains(RFlags::FLAGS_OF),
+        JmpCond::Jno => !rflags.contains(RFlags::FLAGS_OF),
+        JmpCond::Jp => rflags.contains(RFlags::FLAGS_PF),
+        JmpCond::Jnp => !rflags.contains(RFlags::FLAGS_PF),
+        // only ones that dont depend on rflags
+        JmpCond::Jrcxz => vm.regs[Register::Rcx as usize] == 0,
+        JmpCond::Jecxz => vm.regs[Register::Rcx as usize] as u32 == 0,
+    }
+}
+
+#[handler]
+pub unsafe fn jmp(vm: &mut Machine, _op_size: OpSize) {
+    let do_jmp = condition(vm, JmpCond::try_from(*vm.pc).unwrap());
 
     vm.pc = vm.pc.add(1); // skip jmpcond
 
@@ -40,4 +59,16 @@ pub unsafe fn jmp(vm: &mut Machine, _op_size: OpSize) {
     } else {
         vm.pc = vm.pc.add(size_of::<u64>());
     }
-}
\ No newline at end of file
+}
+
+#[handler]
+pub unsafe fn cond(vm: &mut Machine, op_size: OpSize) {
+    let value = condition(vm, JmpCond::try_from(*vm.pc).unwrap()) as u8;
+    vm.pc = vm.pc.add(1); // skip jmpcond
+
+    match op_size {
+        OpSize::Qword => vm.stack_push(value as u64),
+        OpSize::Dword => vm.stack_push(value as u32),
+        OpSize::Word | OpSize::Byte => vm.stack_push(value as u16),
+    }
+}
@@ -3,6 +3,7 @@ pub mod store;
 pub mod r#const;
 pub mod load;
 pub mod cmp;
+pub mod test;
 pub mod sub;
 pub mod mul;
 pub mod and;
@@ -10,10 +11,19 @@ pub mod or;
 pub mod xor;
 pub mod not;
 pub mod div;
+pub mod shift;
 pub mod rot;
 pub mod comb;
 pub mod split;
+pub mod ext;
+pub mod atomic;
+pub mod bit;
+pub mod string;
+pub mod packed;
+pub mod float;
+pub mod flags;
 pub mod jmp;
 pub mod reloc;
 pub mod ctx;
 pub mod exec;
+pub mod call;
@@ -1,14 +1,21 @@
+use x86::bits64::rflags::RFlags;
 use crate::{Machine, OpSize};
 
+/// Multiplies in `$wide` and sets cf and of if the product doesnt fit `$bit`. Operands are popped as
+/// `$save_bit`, bytes are stored as words on the stack, and the product is pushed as `$push`
 macro_rules! mul_save_flags {
-    ($self:ident, $bit:ident, $save_bit:ident) => {{
-        let (op2, op1) = unsafe { ($self.stack_pop::<$save_bit>() as $bit, $self.stack_pop::<$save_bit>() as $bit) };
+    ($self:ident, $save_bit:ident, $bit:ident, $wide:ident, $push:ident) => {{
+        let (op2, op1) = unsafe {
+            ($self.stack_pop::<$save_bit>() as $bit as $wide, $self.stack_pop::<$save_bit>() as $bit as $wide)
+        };
 
         let result = op1.wrapping_mul(op2);
-        // todo of and cf, find out which flags are used (doc says undefined)
-        $crate::calculate_rflags!($self, op1, op2, result, ZF, OF);
+        // sf, zf, af and pf are undefined and left untouched
+        let mut rflags = RFlags::from_bits_truncate($self.rflags);
+        rflags.set(RFlags::FLAGS_CF | RFlags::FLAGS_OF, result != result as $bit as $wide);
+        $self.rflags = rflags.bits();
 
-        unsafe { $self.stack_push::<$bit>(result); }
+        unsafe { $self.stack_push(result as $push); }
     }}
 }
 
@@ -16,13 +23,35 @@ use mul_save_flags;
 use vm_proc::handler;
 use crate::macros::binary_op;
 
+/// Pushes the double width product, which is split into the register halves afterwards
 #[handler]
 pub fn mul(vm: &mut Machine, op_size: OpSize) {
     match op_size {
-        OpSize::Qword => mul_save_flags!(vm, u128, u64),
-        OpSize::Dword => mul_save_flags!(vm, u64, u32),
-        OpSize::Word => mul_save_flags!(vm, u32, u16),
-        OpSize::Byte => mul_save_flags!(vm, u16, u16),
+        OpSize::Qword => mul_save_flags!(vm, u64, u64, u128, u128),
+        OpSize::Dword => mul_save_flags!(vm, u32, u32, u64, u64),
+        OpSize::Word => mul_save_flags!(vm, u16, u16, u32, u32),
+        OpSize::Byte => mul_save_flags!(vm, u16, u8, u16, u16),
+    }
+}
+
+#[handler]
+pub fn imul(vm: &mut Machine, op_size: OpSize) {
+    match op_size {
+        OpSize::Qword => mul_save_flags!(vm, u64, i64, i128, i128),
+        OpSize::Dword => mul_save_flags!(vm, u32, i32, i64, i64),
+        OpSize::Word => mul_save_flags!(vm, u16, i16, i32, i32),
+        OpSize::Byte => mul_save_flags!(vm, u16, i8, i16, i16),
+    }
+}
+
+/// Two and three operand imul, only the low half of the product is kept
+#[handler]
+pub fn imul_trunc(vm: &mut Machine, op_size: OpSize) {
+    match op_size {
+        OpSize::Qword => mul_save_flags!(vm, u64, i64, i128, u64),
+        OpSize::Dword => mul_save_flags!(vm, u32, i32, i64, u32),
+        OpSize::Word => mul_save_flags!(vm, u16, i16, i32, u16),
+        OpSize::Byte => unreachable!(),
     }
 }
 
@@ -30,4 +59,3 @@ pub fn mul(vm: &mut Machine, op_size: OpSize) {
 pub fn vm_mul(vm: &mut Machine, _op_size: OpSize) {
     binary_op!(vm, wrapping_mul)
 }
-
@@ -0,0 +1,59 @@
+use vm_proc::handler;
+use crate::{Machine, OpSize};
+use crate::shared::PackedOp;
+
+/// Pops the source and the destination as u128 and applies `$op` to each `$bit` lane
+macro_rules! lanes {
+    ($vm:ident, $bit:ty, |$dst:ident, $src:ident| $op:expr) => {{
+        let src = $vm.stack_pop::<u128>();
+        let dst = $vm.stack_pop::<u128>();
+        let mut result = 0u128;
+        for shift in (0..128).step_by(<$bit>::BITS as usize) {
+            let ($dst, $src) = ((dst >> shift) as $bit, (src >> shift) as $bit);
+            result |= ($op as u128) << shift;
+        }
+        $vm.stack_push(result);
+    }};
+}
+
+macro_rules! packed_op {
+    ($vm:ident, $op:ident, $bit:ty) => {{
+        match $op {
+            PackedOp::Add => lanes!($vm, $bit, |dst, src| dst.wrapping_add(src)),
+            PackedOp::Sub => lanes!($vm, $bit, |dst, src| dst.wrapping_sub(src)),
+            PackedOp::And => lanes!($vm, $bit, |dst, src| dst & src),
+            PackedOp::AndNot => lanes!($vm, $bit, |dst, src| !dst & src),
+            PackedOp::Or => lanes!($vm, $bit, |dst, src| dst | src),
+            PackedOp::Xor => lanes!($vm, $bit, |dst, src| dst ^ src),
+            PackedOp::CmpEq => lanes!($vm, $bit, |dst, src| if dst == src { <$bit>::MAX } else { 0 }),
+        }
+    }}
+}
+
+/// Packed integer operations on xmm values, the op size is the lane size. Packed instructions don't
+/// touch rflags
+#[handler]
+pub unsafe fn packed(vm: &mut Machine, op_size: OpSize) {
+    let op = PackedOp::try_from(*vm.pc).unwrap();
+    vm.pc = vm.pc.add(1); // skip packed op
+
+    match op_size {
+        OpSize::Qword => packed_op!(vm, op, u64),
+        OpSize::Dword => packed_op!(vm, op, u32),
+        OpSize::Word => packed_op!(vm, op, u16),
+        OpSize::Byte => packed_op!(vm, op, u8),
+    }
+}
+
+/// Pops the order and the source, each 2 bits of the order select the source dword of a lane
+#[handler]
+pub unsafe fn pshufd(vm: &mut Machine, _op_size: OpSize) {
+    let order = vm.stack_pop::<u16>() as u8;
+    let src = vm.stack_pop::<u128>();
+    let mut result = 0u128;
+    for lane in 0..4 {
+        let select = (order >> (lane * 2)) & 3;
+        result |= (src >> (select * 32) & 0xffff_ffff) << (lane * 32);
+    }
+    vm.stack_push(result);
+}
@@ -1,18 +1,114 @@
+use core::mem::size_of;
+use x86::bits64::rflags::RFlags;
 use vm_proc::handler;
-use crate::{rotate, Machine, OpSize};
+use crate::{Machine, OpSize};
+
+macro_rules! rot_save_flags {
+    ($self:ident, $op_size:ident, $op:ident) => {{
+       match $op_size {
+            OpSize::Qword => rot_save_flags!($self, u64, $op;),
+            OpSize::Dword => rot_save_flags!($self, u32, $op;),
+            OpSize::Word => rot_save_flags!($self, u16, $op;),
+            OpSize::Byte => rot_save_flags!($self, u8, $op;),
+        }
+    }};
+    ($self:ident, $bit:ident, $op:ident;) => {{
+        // count is always pushed as byte (imm8 or cl), cpu masks it to 5 bits, 6 for 64 bit
+        let count = unsafe { $self.stack_pop::<u16>() } as u32
+            & if size_of::<$bit>() == 8 { 0x3f } else { 0x1f };
+        let op1 = if size_of::<$bit>() == 1 {
+            unsafe { $self.stack_pop::<u16>() as $bit }
+        } else {
+            unsafe { $self.stack_pop::<$bit>() }
+        };
+
+        // rotates only touch cf and of, a masked count of 0 doesnt affect them either
+        let result = if count == 0 {
+            op1
+        } else {
+            let mut rflags = RFlags::from_bits_truncate($self.rflags);
+            let (result, cf) = $op!(op1, count, rflags.contains(RFlags::FLAGS_CF), $bit);
+            rflags.set(RFlags::FLAGS_CF, cf);
+            rflags.set(RFlags::FLAGS_OF, of!(result, cf, $op, $bit));
+            $self.rflags = rflags.bits();
+            result
+        };
+
+        if size_of::<$bit>() == 1 {
+            unsafe { $self.stack_push(result as u16); }
+        } else {
+            unsafe { $self.stack_push(result); }
+        }
+    }}
+}
+
+// of is only defined for a count of 1, left rotates xor the new msb with cf, right rotates the two
+// most significant bits of the result
+macro_rules! of {
+    ($result:ident, $cf:ident, rol_flags, $bit:ident) => {
+        ($result >> ($bit::BITS - 1) == 1) ^ $cf
+    };
+    ($result:ident, $cf:ident, rcl_flags, $bit:ident) => {
+        of!($result, $cf, rol_flags, $bit)
+    };
+    ($result:ident, $cf:ident, $op:ident, $bit:ident) => {
+        (($result >> ($bit::BITS - 1)) ^ ($result >> ($bit::BITS - 2))) & 1 == 1
+    };
+}
+
+// cf receives the bit rotated into the lsb
+macro_rules! rol_flags {
+    ($op1:ident, $count:ident, $carry:expr, $bit:ident) => {{
+        let result = $op1.rotate_left($count % $bit::BITS);
+        (result, result & 1 == 1)
+    }};
+}
+
+// cf receives the bit rotated into the msb
+macro_rules! ror_flags {
+    ($op1:ident, $count:ident, $carry:expr, $bit:ident) => {{
+        let result = $op1.rotate_right($count % $bit::BITS);
+        (result, result >> ($bit::BITS - 1) == 1)
+    }};
+}
+
+// rotates through carry treat cf as the bit above the msb, e.g. rcl al rotates 9 bits
+macro_rules! rcl_flags {
+    ($op1:ident, $count:ident, $carry:expr, $bit:ident) => {{
+        let width = $bit::BITS + 1;
+        let count = $count % width;
+        let value = ($carry as u128) << $bit::BITS | $op1 as u128;
+        let rotated = (value << count | value >> (width - count)) & ((1 << width) - 1);
+        (rotated as $bit, rotated >> $bit::BITS == 1)
+    }};
+}
+
+macro_rules! rcr_flags {
+    ($op1:ident, $count:ident, $carry:expr, $bit:ident) => {{
+        let width = $bit::BITS + 1;
+        let count = $count % width;
+        let value = ($carry as u128) << $bit::BITS | $op1 as u128;
+        let rotated = (value >> count | value << (width - count)) & ((1 << width) - 1);
+        (rotated as $bit, rotated >> $bit::BITS == 1)
+    }};
+}
 
 #[handler]
 pub fn rot_r(vm: &mut Machine, op_size: OpSize) {
-    match op_size {
-        OpSize::Word => rotate!(vm, u16, rotate_right),
-        _ => unreachable!()
-    }
+    rot_save_flags!(vm, op_size, ror_flags);
 }
 
 #[handler]
 pub fn rot_l(vm: &mut Machine, op_size: OpSize) {
-    match op_size {
-        OpSize::Word => rotate!(vm, u16, rotate_left),
-        _ => unreachable!()
-    }
-}
\ No newline at end of file
+    rot_save_flags!(vm, op_size, rol_flags);
+}
+
+#[handler]
+pub fn rcr(vm: &mut Machine, op_size: OpSize) {
+    rot_save_flags!(vm, op_size, rcr_flags);
+}
+
+#[handler]
+pub fn rcl(vm: &mut Machine, op_size: OpSize) {
+    rot_save_flags!(vm, op_size, rcl_flags);
+}
@@ -0,0 +1,139 @@
+use core::mem::size_of;
+use x86::bits64::rflags::RFlags;
+use vm_proc::handler;
+use crate::{calculate_rflags, Machine, OpSize};
+use crate::macros::get_msb;
+
+macro_rules! shift_save_flags {
+    ($self:ident, $op_size:ident, $op:ident) => {{
+       match $op_size {
+            OpSize::Qword => shift_save_flags!($self, u64, i64, $op;),
+            OpSize::Dword => shift_save_flags!($self, u32, i32, $op;),
+            OpSize::Word => shift_save_flags!($self, u16, i16, $op;),
+            OpSize::Byte => shift_save_flags!($self, u8, i8, $op;),
+        }
+    }};
+    ($self:ident, $bit:ident, $signed:ident, $op:ident;) => {{
+        // count is always pushed as byte (imm8 or cl), cpu masks it to 5 bits, 6 for 64 bit
+        let count = unsafe { $self.stack_pop::<u16>() } as u32
+            & if size_of::<$bit>() == 8 { 0x3f } else { 0x1f };
+        let op1 = if size_of::<$bit>() == 1 {
+            unsafe { $self.stack_pop::<u16>() as $bit }
+        } else {
+            unsafe { $self.stack_pop::<$bit>() }
+        };
+
+        // a masked count of 0 doesnt affect any flags
+        let result = if count == 0 {
+            op1
+        } else {
+            let (result, cf, of) = $op!(op1, count, $bit, $signed);
+            let mut rflags = RFlags::from_bits_truncate($self.rflags);
+            rflags.set(RFlags::FLAGS_CF, cf);
+            rflags.set(RFlags::FLAGS_OF, of);
+            $self.rflags = rflags.bits();
+            calculate_rflags!($self, op1, count, result, SF, ZF, PF);
+            result
+        };
+
+        if size_of::<$bit>() == 1 {
+            unsafe { $self.stack_push(result as u16); }
+        } else {
+            unsafe { $self.stack_push(result); }
+        }
+    }}
+}
+
+// cf is the last bit shifted out, of is only defined for a count of 1
+macro_rules! shl_flags {
+    ($op1:ident, $count:ident, $bit:ident, $signed:ident) => {{
+        let result = $op1.checked_shl($count).unwrap_or(0);
+        let cf = $count <= $bit::BITS && ($op1 >> ($bit::BITS - $count)) & 1 == 1;
+        (result, cf, (get_msb(result) == 1) ^ cf)
+    }};
+}
+
+macro_rules! shr_flags {
+    ($op1:ident, $count:ident, $bit:ident, $signed:ident) => {{
+        let result = $op1.checked_shr($count).unwrap_or(0);
+        let cf = $op1.checked_shr($count - 1).unwrap_or(0) & 1 == 1;
+        (result, cf, get_msb($op1) == 1)
+    }};
+}
+
+macro_rules! sar_flags {
+    ($op1:ident, $count:ident, $bit:ident, $signed:ident) => {{
+        // shifting by more than the width fills with the sign bit
+        let value = $op1 as $signed;
+        let result = (value >> $count.min($bit::BITS - 1)) as $bit;
+        let cf = (value >> ($count - 1).min($bit::BITS - 1)) & 1 == 1;
+        (result, cf, false)
+    }};
+}
+
+#[handler]
+pub fn shl(vm: &mut Machine, op_size: OpSize) {
+    shift_save_flags!(vm, op_size, shl_flags);
+}
+
+#[handler]
+pub fn shr(vm: &mut Machine, op_size: OpSize) {
+    shift_save_flags!(vm, op_size, shr_flags);
+}
+
+#[handler]
+pub fn sar(vm: &mut Machine, op_size: OpSize) {
+    shift_save_flags!(vm, op_size, sar_flags);
+}
+
+/// Double precision shifts pop the count, the source shifted in and the destination, there is no byte form.
+/// The operands are concatenated into `$wide` and shifted at once
+macro_rules! double_shift_save_flags {
+    ($self:ident, $op_size:ident, $left:literal) => {{
+       match $op_size {
+            OpSize::Qword => double_shift_save_flags!($self, u64, u128, $left;),
+            OpSize::Dword => double_shift_save_flags!($self, u32, u64, $left;),
+            OpSize::Word => double_shift_save_flags!($self, u16, u32, $left;),
+            OpSize::Byte => unreachable!(),
+        }
+    }};
+    ($self:ident, $bit:ident, $wide:ident, $left:literal;) => {{
+        let count = unsafe { $self.stack_pop::<u16>() } as u32
+            & if size_of::<$bit>() == 8 { 0x3f } else { 0x1f };
+        let src = unsafe { $self.stack_pop::<$bit>() };
+        let dst = unsafe { $self.stack_pop::<$bit>() };
+
+        // counts above 16 are undefined for 16 bit operands, they shift in bits of src again here
+        let result = if count == 0 {
+            dst
+        } else {
+            let (result, cf) = if $left {
+                let wide = (dst as $wide) << $bit::BITS | src as $wide;
+                ((wide << count >> $bit::BITS) as $bit, (wide >> ($bit::BITS * 2 - count)) & 1 == 1)
+            } else {
+                let wide = (src as $wide) << $bit::BITS | dst as $wide;
+                ((wide >> count) as $bit, (wide >> (count - 1)) & 1 == 1)
+            };
+
+            // of is only defined for a count of 1 and set if the sign changed
+            let mut rflags = RFlags::from_bits_truncate($self.rflags);
+            rflags.set(RFlags::FLAGS_CF, cf);
+            rflags.set(RFlags::FLAGS_OF, get_msb(result) != get_msb(dst));
+            $self.rflags = rflags.bits();
+            calculate_rflags!($self, dst, src, result, SF, ZF, PF);
+            result
+        };
+
+        unsafe { $self.stack_push(result); }
+    }}
+}
+
+#[handler]
+pub fn shld(vm: &mut Machine, op_size: OpSize) {
+    double_shift_save_flags!(vm, op_size, true);
+}
+
+#[handler]
+pub fn shrd(vm: &mut Machine, op_size: OpSize) {
+    double_shift_save_flags!(vm, op_size, false);
+}
@@ -0,0 +1,67 @@
+use core::mem::size_of;
+use x86::bits64::rflags::RFlags;
+use vm_proc::handler;
+use crate::{calculate_rflags, Machine, OpSize};
+use crate::shared::{Register, StringOp};
+
+/// Performs one element of a string instruction on rsi, rdi and rax and advances the pointers by the
+/// element size, backwards if df is set. Rep loops are built around it in bytecode.
+/// `$keep` masks the bits of rax that survive lods.
+macro_rules! string_op {
+    ($vm:ident, $op:ident, $bit:ty, $keep:expr) => {{
+        let size = size_of::<$bit>() as u64;
+        let delta = match RFlags::from_bits_truncate($vm.rflags).contains(RFlags::FLAGS_DF) {
+            true => size.wrapping_neg(),
+            false => size,
+        };
+        let rsi = $vm.regs[Register::Rsi as usize];
+        let rdi = $vm.regs[Register::Rdi as usize];
+
+        match $op {
+            StringOp::Movs => {
+                (rdi as *mut $bit).write_unaligned((rsi as *const $bit).read_unaligned());
+                $vm.regs[Register::Rsi as usize] = rsi.wrapping_add(delta);
+                $vm.regs[Register::Rdi as usize] = rdi.wrapping_add(delta);
+            }
+            StringOp::Stos => {
+                (rdi as *mut $bit).write_unaligned($vm.regs[Register::Rax as usize] as $bit);
+                $vm.regs[Register::Rdi as usize] = rdi.wrapping_add(delta);
+            }
+            StringOp::Lods => {
+                let value = (rsi as *const $bit).read_unaligned();
+                let rax = &mut $vm.regs[Register::Rax as usize];
+                *rax = (*rax & $keep) | value as u64;
+                $vm.regs[Register::Rsi as usize] = rsi.wrapping_add(delta);
+            }
+            StringOp::Cmps => {
+                let op1 = (rsi as *const $bit).read_unaligned();
+                let op2 = (rdi as *const $bit).read_unaligned();
+                let result = op1.wrapping_sub(op2);
+                calculate_rflags!($vm, op1, op2, result, OF_SUB, SF, ZF, AF, PF, CF_SUB);
+                $vm.regs[Register::Rsi as usize] = rsi.wrapping_add(delta);
+                $vm.regs[Register::Rdi as usize] = rdi.wrapping_add(delta);
+            }
+            StringOp::Scas => {
+                let op1 = $vm.regs[Register::Rax as usize] as $bit;
+                let op2 = (rdi as *const $bit).read_unaligned();
+                let result = op1.wrapping_sub(op2);
+                calculate_rflags!($vm, op1, op2, result, OF_SUB, SF, ZF, AF, PF, CF_SUB);
+                $vm.regs[Register::Rdi as usize] = rdi.wrapping_add(delta);
+            }
+        }
+    }}
+}
+
+#[handler]
+pub unsafe fn string(vm: &mut Machine, op_size: OpSize) {
+    let op = StringOp::try_from(*vm.pc).unwrap();
+    vm.pc = vm.pc.add(1); // skip string op
+
+    match op_size {
+        OpSize::Qword => string_op!(vm, op, u64, 0),
+        // 32 bit writes zero extend
+        OpSize::Dword => string_op!(vm, op, u32, 0),
+        OpSize::Word => string_op!(vm, op, u16, !0xffff),
+        OpSize::Byte => string_op!(vm, op, u8, !0xff),
+    }
+}
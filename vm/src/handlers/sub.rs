@@ -1,10 +1,51 @@
 use vm_proc::handler;
-use crate::{binary_op_save_flags, Machine, OpSize};
+use crate::{binary_op_carry_save_flags, binary_op_save_flags, calculate_rflags, step_save_flags, Machine, OpSize};
 use crate::macros::binary_op;
 
+// neg is sub from zero, cf is set for any non zero operand
+macro_rules! neg_save_flags {
+    ($self:ident, $bit:ident) => {{
+        let op2 = if core::mem::size_of::<$bit>() == 1 {
+            unsafe { $self.stack_pop::<u16>() as $bit }
+        } else {
+            unsafe { $self.stack_pop::<$bit>() }
+        };
+        let op1: $bit = 0;
+
+        let result = op1.wrapping_sub(op2);
+        calculate_rflags!($self, op1, op2, result, OF_SUB, SF, ZF, AF, PF, CF_SUB);
+
+        if core::mem::size_of::<$bit>() == 1 {
+            unsafe { $self.stack_push(result as u16); }
+        } else {
+            unsafe { $self.stack_push(result); }
+        }
+    }}
+}
+
 #[handler]
 pub fn sub(vm: &mut Machine, op_size: OpSize) {
-    binary_op_save_flags!(vm, op_size, wrapping_sub, OF, SF, ZF, PF, CF_SUB);
+    binary_op_save_flags!(vm, op_size, wrapping_sub, OF_SUB, SF, ZF, AF, PF, CF_SUB);
+}
+
+#[handler]
+pub fn sbb(vm: &mut Machine, op_size: OpSize) {
+    binary_op_carry_save_flags!(vm, op_size, wrapping_sub, OF_SBB, SF, ZF, AF, PF, CF_SBB);
+}
+
+#[handler]
+pub fn neg(vm: &mut Machine, op_size: OpSize) {
+    match op_size {
+        OpSize::Qword => neg_save_flags!(vm, u64),
+        OpSize::Dword => neg_save_flags!(vm, u32),
+        OpSize::Word => neg_save_flags!(vm, u16),
+        OpSize::Byte => neg_save_flags!(vm, u8),
+    }
+}
+
+#[handler]
+pub fn dec(vm: &mut Machine, op_size: OpSize) {
+    step_save_flags!(vm, op_size, wrapping_sub, OF_SUB);
 }
 
 #[handler]
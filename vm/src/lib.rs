@@ -343,28 +343,65 @@ impl Machine {
                 Opcode::StoreXmm => handlers::store::store_xmm(self, op_size),
                 Opcode::StoreReg => handlers::store::store_reg(self, op_size),
                 Opcode::StoreRegZx => handlers::store::store_reg_zx(self, op_size),
+                Opcode::SignExtend => handlers::ext::sign_extend(self, op_size),
                 Opcode::Div => handlers::div::div(self, op_size),
                 Opcode::IDiv => handlers::div::idiv(self, op_size),
-                Opcode::Shr => handlers::div::shr(self, op_size), // possibly unfinished
+                Opcode::Shl => handlers::shift::shl(self, op_size),
+                Opcode::Shr => handlers::shift::shr(self, op_size),
+                Opcode::Sar => handlers::shift::sar(self, op_size),
                 Opcode::Combine => handlers::comb::combine(self, op_size),
                 Opcode::Split => handlers::split::split(self, op_size),
                 Opcode::Mul => handlers::mul::mul(self, op_size),
+                Opcode::IMul => handlers::mul::imul(self, op_size),
+                Opcode::IMulTrunc => handlers::mul::imul_trunc(self, op_size),
                 Opcode::Add => handlers::add::add(self, op_size),
+                Opcode::Adc => handlers::add::adc(self, op_size),
                 Opcode::Sub => handlers::sub::sub(self, op_size),
+                Opcode::Sbb => handlers::sub::sbb(self, op_size),
+                Opcode::Neg => handlers::sub::neg(self, op_size),
+                Opcode::Inc => handlers::add::inc(self, op_size),
+                Opcode::Dec => handlers::sub::dec(self, op_size),
                 Opcode::And => handlers::and::and(self, op_size),
                 Opcode::Or => handlers::or::or(self, op_size),
                 Opcode::Xor => handlers::xor::xor(self, op_size),
                 Opcode::Not => handlers::not::not(self, op_size),
                 Opcode::Cmp => handlers::cmp::cmp(self, op_size),
+                Opcode::Test => handlers::test::test(self, op_size),
+                Opcode::Atomic => handlers::atomic::atomic(self, op_size),
+                Opcode::String => handlers::string::string(self, op_size),
+                Opcode::Packed => handlers::packed::packed(self, op_size),
+                Opcode::Pshufd => handlers::packed::pshufd(self, op_size),
+                Opcode::Float => handlers::float::float(self, op_size),
+                Opcode::Bt => handlers::bit::bt(self, op_size),
+                Opcode::Bts => handlers::bit::bts(self, op_size),
+                Opcode::Btr => handlers::bit::btr(self, op_size),
+                Opcode::Btc => handlers::bit::btc(self, op_size),
+                Opcode::BitAddr => handlers::bit::bit_addr(self, op_size),
+                Opcode::Bsf => handlers::bit::bsf(self, op_size),
+                Opcode::Bsr => handlers::bit::bsr(self, op_size),
+                Opcode::Tzcnt => handlers::bit::tzcnt(self, op_size),
+                Opcode::Lzcnt => handlers::bit::lzcnt(self, op_size),
+                Opcode::Popcnt => handlers::bit::popcnt(self, op_size),
+                Opcode::Bswap => handlers::bit::bswap(self, op_size),
                 Opcode::RotR => handlers::rot::rot_r(self, op_size),
                 Opcode::RotL => handlers::rot::rot_l(self, op_size),
+                Opcode::Rcr => handlers::rot::rcr(self, op_size),
+                Opcode::Rcl => handlers::rot::rcl(self, op_size),
+                Opcode::Popf => handlers::flags::popf(self, op_size),
+                Opcode::Shld => handlers::shift::shld(self, op_size),
+                Opcode::Shrd => handlers::shift::shrd(self, op_size),
                 Opcode::Jmp => handlers::jmp::jmp(self, op_size),
+                Opcode::Cond => handlers::jmp::cond(self, op_size),
                 Opcode::VmAdd => handlers::add::vm_add(self, op_size),
                 Opcode::VmSub => handlers::sub::vm_sub(self, op_size),
                 Opcode::VmMul => handlers::mul::vm_mul(self, op_size),
                 Opcode::VmReloc => handlers::reloc::vm_reloc(self, op_size),
                 Opcode::Vmctx => handlers::ctx::vm_ctx(self, op_size),
+                Opcode::GsBase => handlers::ctx::gs_base(self, op_size),
+                Opcode::FsBase => handlers::ctx::fs_base(self, op_size),
                 Opcode::VmExec => handlers::exec::vm_exec(self, op_size),
+                Opcode::VmCall => handlers::call::vm_call(self, op_size),
+                Opcode::VmRet => handlers::call::vm_ret(self, op_size),
                 Opcode::VmExit => break,
             }
         }
@@ -14,8 +14,7 @@ pub fn get_msb<N>(n: N) -> N
 }
 
 macro_rules! calculate_rflags {
-    // of also sets cf for now
-    ($self:ident, $op1:ident, $op2: ident, $result:ident, OF) => {{
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, OF_ADD) => {{
         use x86::bits64::rflags::RFlags;
         let mut rflags = RFlags::from_bits_truncate($self.rflags);
         rflags.set(RFlags::FLAGS_OF, (($crate::macros::get_msb($op1) == 0 && $crate::macros::get_msb($op2) == 0)
@@ -24,6 +23,15 @@ macro_rules! calculate_rflags {
         );
         $self.rflags = rflags.bits();
     }};
+    // operands with different signs overflow if the result sign differs from op1
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, OF_SUB) => {{
+        use x86::bits64::rflags::RFlags;
+        let mut rflags = RFlags::from_bits_truncate($self.rflags);
+        rflags.set(RFlags::FLAGS_OF, $crate::macros::get_msb($op1) != $crate::macros::get_msb($op2)
+            && $crate::macros::get_msb($result) != $crate::macros::get_msb($op1)
+        );
+        $self.rflags = rflags.bits();
+    }};
     ($self:ident, $op1:ident, $op2: ident, $result:ident, CF_ADD) => {{
         use x86::bits64::rflags::RFlags;
         let mut rflags = RFlags::from_bits_truncate($self.rflags);
@@ -36,8 +44,55 @@ macro_rules! calculate_rflags {
         rflags.set(RFlags::FLAGS_CF, $result > $op1);
         $self.rflags = rflags.bits();
     }};
-     ($self:ident, $op1:ident, $op2: ident, $result:ident, AF) => {{
-        unimplemented!()
+    // carry in is recovered from the operands, result == op1 is only a carry out
+    // if op2 + carry wrapped around
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, CF_ADC) => {{
+        use x86::bits64::rflags::RFlags;
+        let carry = $result.wrapping_sub($op1).wrapping_sub($op2) != 0;
+        let mut rflags = RFlags::from_bits_truncate($self.rflags);
+        rflags.set(RFlags::FLAGS_CF, $result < $op1 || ($result == $op1 && carry));
+        $self.rflags = rflags.bits();
+    }};
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, CF_SBB) => {{
+        use x86::bits64::rflags::RFlags;
+        let carry = $op1.wrapping_sub($op2).wrapping_sub($result) != 0;
+        let mut rflags = RFlags::from_bits_truncate($self.rflags);
+        rflags.set(RFlags::FLAGS_CF, $op1 < $op2 || ($op1 == $op2 && carry));
+        $self.rflags = rflags.bits();
+    }};
+    // a carry in of 1 can't change the sign rules of add and sub
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, OF_ADC) => {{
+        $crate::calculate_rflags!($self, $op1, $op2, $result, OF_ADD);
+    }};
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, OF_SBB) => {{
+        $crate::calculate_rflags!($self, $op1, $op2, $result, OF_SUB);
+    }};
+    // logical instructions always clear cf and of
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, CF_CLEAR) => {{
+        use x86::bits64::rflags::RFlags;
+        let mut rflags = RFlags::from_bits_truncate($self.rflags);
+        rflags.remove(RFlags::FLAGS_CF);
+        $self.rflags = rflags.bits();
+    }};
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, OF_CLEAR) => {{
+        use x86::bits64::rflags::RFlags;
+        let mut rflags = RFlags::from_bits_truncate($self.rflags);
+        rflags.remove(RFlags::FLAGS_OF);
+        $self.rflags = rflags.bits();
+    }};
+    // carry or borrow out of bit 3, the bit 4 of the operands and result only differs if there was one
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, AF) => {{
+        use x86::bits64::rflags::RFlags;
+        let mut rflags = RFlags::from_bits_truncate($self.rflags);
+        rflags.set(RFlags::FLAGS_AF, ($op1 ^ $op2 ^ $result) & 0x10 != 0);
+        $self.rflags = rflags.bits();
+    }};
+    // af is undefined after logical instructions, the cpu clears it
+    ($self:ident, $op1:ident, $op2: ident, $result:ident, AF_CLEAR) => {{
+        use x86::bits64::rflags::RFlags;
+        let mut rflags = RFlags::from_bits_truncate($self.rflags);
+        rflags.remove(RFlags::FLAGS_AF);
+        $self.rflags = rflags.bits();
     }};
     ($self:ident, $op1:ident, $op2: ident, $result:ident, ZF) => {{
         use x86::bits64::rflags::RFlags;
@@ -48,7 +103,8 @@ macro_rules! calculate_rflags {
     ($self:ident, $op1:ident, $op2: ident, $result:ident, PF) => {{
         use x86::bits64::rflags::RFlags;
         let mut rflags = RFlags::from_bits_truncate($self.rflags);
-        rflags.set(RFlags::FLAGS_PF, $result.count_ones() % 2 != 0);
+        // even parity of the low byte only
+        rflags.set(RFlags::FLAGS_PF, ($result as u8).count_ones() % 2 == 0);
         $self.rflags = rflags.bits();
     }};
     ($self:ident, $op1:ident, $op2: ident, $result:ident, SF) => {{
@@ -77,16 +133,16 @@ macro_rules! binary_op {
 
 pub(crate) use binary_op;
 
-macro_rules! binary_op_sized {
-    ($self:ident, $op_size:ident, $op:ident) => {{
+macro_rules! binary_op_save_flags {
+    ($self:ident, $op_size:ident, $op:ident $(, $rflag:ident)*) => {{
        match $op_size {
-            OpSize::Qword => binary_op_sized!($self, u64, $op;),
-            OpSize::Dword => binary_op_sized!($self, u32, $op;),
-            OpSize::Word => binary_op_sized!($self, u16, $op;),
-            OpSize::Byte => binary_op_sized!($self, u8, $op;),
+            OpSize::Qword => binary_op_save_flags!($self, u64, $op, $($rflag),*;),
+            OpSize::Dword => binary_op_save_flags!($self, u32, $op, $($rflag),*;),
+            OpSize::Word => binary_op_save_flags!($self, u16, $op, $($rflag),*;),
+            OpSize::Byte => binary_op_save_flags!($self, u8, $op, $($rflag),*;),
         }
     }};
-    ($self:ident, $bit:ident, $op:ident;) => {{
+    ($self:ident, $bit:ident, $op:ident $(, $rflag:ident)* ;) => {{
         let (op2, op1) = if core::mem::size_of::<$bit>() == 1 {
             unsafe { ($self.stack_pop::<u16>() as $bit, $self.stack_pop::<u16>() as $bit) }
         } else {
@@ -95,6 +151,10 @@ macro_rules! binary_op_sized {
 
         let result = op1.$op(op2);
 
+        $crate::calculate_rflags!($self, op1, op2, result, $($rflag),*);
+
+        //$self.set_rflags();
+
         if core::mem::size_of::<$bit>() == 1 {
             unsafe { $self.stack_push(result as u16); }
         } else {
@@ -103,15 +163,15 @@ macro_rules! binary_op_sized {
     }}
 }
 
-pub(crate) use binary_op_sized;
+pub(crate) use binary_op_save_flags;
 
-macro_rules! binary_op_save_flags {
+macro_rules! binary_op_carry_save_flags {
     ($self:ident, $op_size:ident, $op:ident $(, $rflag:ident)*) => {{
        match $op_size {
-            OpSize::Qword => binary_op_save_flags!($self, u64, $op, $($rflag),*;),
-            OpSize::Dword => binary_op_save_flags!($self, u32, $op, $($rflag),*;),
-            OpSize::Word => binary_op_save_flags!($self, u16, $op, $($rflag),*;),
-            OpSize::Byte => binary_op_save_flags!($self, u8, $op, $($rflag),*;),
+            OpSize::Qword => binary_op_carry_save_flags!($self, u64, $op, $($rflag),*;),
+            OpSize::Dword => binary_op_carry_save_flags!($self, u32, $op, $($rflag),*;),
+            OpSize::Word => binary_op_carry_save_flags!($self, u16, $op, $($rflag),*;),
+            OpSize::Byte => binary_op_carry_save_flags!($self, u8, $op, $($rflag),*;),
         }
     }};
     ($self:ident, $bit:ident, $op:ident $(, $rflag:ident)* ;) => {{
@@ -121,12 +181,12 @@ macro_rules! binary_op_save_flags {
             unsafe { ($self.stack_pop::<$bit>(), $self.stack_pop::<$bit>()) }
         };
 
-        let result = op1.$op(op2);
+        let carry = x86::bits64::rflags::RFlags::from_bits_truncate($self.rflags)
+            .contains(x86::bits64::rflags::RFlags::FLAGS_CF) as $bit;
+        let result = op1.$op(op2).$op(carry);
 
         $crate::calculate_rflags!($self, op1, op2, result, $($rflag),*);
 
-        //$self.set_rflags();
-
         if core::mem::size_of::<$bit>() == 1 {
             unsafe { $self.stack_push(result as u16); }
         } else {
@@ -135,26 +195,30 @@ macro_rules! binary_op_save_flags {
     }}
 }
 
-pub(crate) use binary_op_save_flags;
+pub(crate) use binary_op_carry_save_flags;
 
-macro_rules! binary_op_arg1 {
-    ($self:ident, $op_size:ident, $op:ident) => {{
+/// Inc and dec are add and sub of 1 that leave cf untouched
+macro_rules! step_save_flags {
+    ($self:ident, $op_size:ident, $op:ident, $of:ident) => {{
        match $op_size {
-            OpSize::Qword => binary_op_arg1!($self, u64, $op;),
-            OpSize::Dword => binary_op_arg1!($self, u32, $op;),
-            OpSize::Word => binary_op_arg1!($self, u16, $op;),
-            OpSize::Byte => binary_op_arg1!($self, u8, $op;),
+            OpSize::Qword => step_save_flags!($self, u64, $op, $of;),
+            OpSize::Dword => step_save_flags!($self, u32, $op, $of;),
+            OpSize::Word => step_save_flags!($self, u16, $op, $of;),
+            OpSize::Byte => step_save_flags!($self, u8, $op, $of;),
         }
     }};
-    ($self:ident, $bit:ident, $op:ident;) => {{
+    ($self:ident, $bit:ident, $op:ident, $of:ident;) => {{
         let op1 = if core::mem::size_of::<$bit>() == 1 {
             unsafe { $self.stack_pop::<u16>() as $bit }
         } else {
             unsafe { $self.stack_pop::<$bit>() }
         };
-        let result = op1.$op();
+        let op2: $bit = 1;
+        let result = op1.$op(op2);
 
-         if core::mem::size_of::<$bit>() == 1 {
+        $crate::calculate_rflags!($self, op1, op2, result, $of, SF, ZF, AF, PF);
+
+        if core::mem::size_of::<$bit>() == 1 {
             unsafe { $self.stack_push(result as u16); }
         } else {
             unsafe { $self.stack_push(result); }
@@ -162,19 +226,26 @@ macro_rules! binary_op_arg1 {
     }}
 }
 
-pub(crate) use binary_op_arg1;
+pub(crate) use step_save_flags;
 
-macro_rules! rotate {
-    ($self:ident, $bit:ident, $op:ident) => {{
+macro_rules! binary_op_arg1 {
+    ($self:ident, $op_size:ident, $op:ident) => {{
+       match $op_size {
+            OpSize::Qword => binary_op_arg1!($self, u64, $op;),
+            OpSize::Dword => binary_op_arg1!($self, u32, $op;),
+            OpSize::Word => binary_op_arg1!($self, u16, $op;),
+            OpSize::Byte => binary_op_arg1!($self, u8, $op;),
+        }
+    }};
+    ($self:ident, $bit:ident, $op:ident;) => {{
         let op1 = if core::mem::size_of::<$bit>() == 1 {
             unsafe { $self.stack_pop::<u16>() as $bit }
         } else {
             unsafe { $self.stack_pop::<$bit>() }
         };
+        let result = op1.$op();
 
-        let result = op1.$op(8);
-
-        if core::mem::size_of::<$bit>() == 1 {
+         if core::mem::size_of::<$bit>() == 1 {
             unsafe { $self.stack_push(result as u16); }
         } else {
             unsafe { $self.stack_push(result); }
@@ -182,4 +253,4 @@ macro_rules! rotate {
     }}
 }
 
-pub(crate) use rotate;
\ No newline at end of file
+pub(crate) use binary_op_arg1;
@@ -10,32 +10,72 @@ pub enum Opcode {
     StoreXmm,
     StoreReg,
     StoreRegZx,
+    SignExtend,
     Add,
+    Adc,
     Sub,
+    Sbb,
+    Neg,
+    Inc,
+    Dec,
     Div,
     IDiv,
+    Shl,
     Shr,
+    Sar,
     Combine,
     Split,
     Mul,
+    IMul,
+    IMulTrunc,
     And,
     Or,
     Xor,
     Not,
     Cmp,
+    Test,
+    Atomic,
+    String,
+    Packed,
+    Pshufd,
+    Float,
+    Bt,
+    Bts,
+    Btr,
+    Btc,
+    BitAddr,
+    Bsf,
+    Bsr,
+    Tzcnt,
+    Lzcnt,
+    Popcnt,
+    Bswap,
     RotR,
     RotL,
+    Rcr,
+    Rcl,
+    Popf,
+    Shld,
+    Shrd,
     //
     Jmp,
+    Cond,
     Vmctx,
+    GsBase,
+    FsBase,
     VmAdd,
     VmMul,
     VmSub,
     VmReloc,
     VmExec,
+    VmCall,
+    VmRet,
     VmExit,
 }
 
+// tags return addresses pushed by vm calls, user mode addresses never have it set
+pub const VIRTUAL_RETURN: u64 = 1 << 63;
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, num_enum::TryFromPrimitive, num_enum::IntoPrimitive)]
 pub enum OpSize {
@@ -57,6 +97,88 @@ pub enum JmpCond {
     Jae, // jnc
     Jle, // Jng
     Jg, // Jnle
+    Jb, // Jc, Jnae
+    Jl, // Jnge
+    Jge, // Jnl
+    Js,
+    Jns,
+    Jo,
+    Jno,
+    Jp, // Jpe
+    Jnp, // Jpo
+    Jrcxz,
+    Jecxz,
+}
+
+// locked read modify write operations, xchg with memory is always locked
+#[repr(u8)]
+#[derive(Clone, Copy)]
+#[derive(Debug, num_enum::TryFromPrimitive, num_enum::IntoPrimitive)]
+pub enum AtomicOp {
+    Xchg,
+    Xadd,
+    CmpXchg,
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Inc,
+    Dec,
+    Not,
+    Bts,
+    Btr,
+    Btc,
+}
+
+// lane wise packed integer operations on xmm values
+#[repr(u8)]
+#[derive(Clone, Copy)]
+#[derive(Debug, num_enum::TryFromPrimitive, num_enum::IntoPrimitive)]
+pub enum PackedOp {
+    Add,
+    Sub,
+    And,
+    AndNot,
+    Or,
+    Xor,
+    CmpEq,
+}
+
+// scalar single and double precision operations, conversions to int take the int size from the op
+#[repr(u8)]
+#[derive(Clone, Copy)]
+#[derive(Debug, num_enum::TryFromPrimitive, num_enum::IntoPrimitive)]
+pub enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Min,
+    Max,
+    Sqrt,
+    UComi,
+    Comi,
+    ToInt32,
+    ToInt64,
+    TruncToInt32,
+    TruncToInt64,
+    FromInt32,
+    FromInt64,
+    // single to double precision or the other way around
+    ToOther,
+}
+
+// single element of a string instruction, rep is a bytecode loop around it
+#[repr(u8)]
+#[derive(Clone, Copy)]
+#[derive(Debug, num_enum::TryFromPrimitive, num_enum::IntoPrimitive)]
+pub enum StringOp {
+    Movs,
+    Stos,
+    Lods,
+    Cmps,
+    Scas,
 }
 
 #[repr(u8)]